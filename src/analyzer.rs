@@ -0,0 +1,202 @@
+//! Oscilloscope and spectrum of the audio output
+
+use std::{
+    f32::consts::PI,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use eframe::egui::{Align2, DragValue, FontId, Pos2, Rect, Sense, Stroke, Ui, Vec2, pos2, vec2};
+use timbre_synth::fft;
+
+/// Samples kept for display, a power of two for the FFT
+const LEN: usize = 4096;
+const HEIGHT: f32 = 120.0;
+/// Lowest frequency and level shown in the spectrum
+const MIN_HZ: f32 = 20.0;
+const MIN_DB: f32 = -100.0;
+
+/// Recent output samples, written by the audio thread without blocking and
+/// read by the GUI.
+///
+/// There is a single writer at a time. The ring holds twice the samples the
+/// GUI reads, so a read is not overwritten while it copies.
+pub struct Tap {
+    ring: Box<[AtomicU32]>,
+    /// Total number of samples written
+    written: AtomicUsize,
+    sample_rate: AtomicU32,
+}
+
+impl Tap {
+    pub fn new() -> Self {
+        Self {
+            ring: (0..2 * LEN).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            sample_rate: AtomicU32::new(48000),
+        }
+    }
+
+    pub fn set_sample_rate(&self, sample_rate: u32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+    }
+
+    /// Records the next output sample
+    pub fn push(&self, value: f32) {
+        let written = self.written.load(Ordering::Relaxed);
+        self.ring[written % self.ring.len()].store(value.to_bits(), Ordering::Relaxed);
+        self.written
+            .store(written.wrapping_add(1), Ordering::Release);
+    }
+
+    /// Copies the latest samples into `buffer`, oldest first
+    fn read(&self, buffer: &mut [f32]) {
+        let written = self.written.load(Ordering::Acquire);
+        let start = written.wrapping_sub(buffer.len());
+        for (i, sample) in buffer.iter_mut().enumerate() {
+            let index = start.wrapping_add(i) % self.ring.len();
+            *sample = f32::from_bits(self.ring[index].load(Ordering::Relaxed));
+        }
+    }
+}
+
+/// Settings and buffers of the analyzer panel
+pub struct Analyzer {
+    /// Time shown by the scope in milliseconds
+    span: f32,
+    samples: Vec<f32>,
+    /// Real and imaginary parts of the spectrum
+    re: Vec<f32>,
+    im: Vec<f32>,
+}
+
+impl Default for Analyzer {
+    fn default() -> Self {
+        Self {
+            span: 10.0,
+            samples: vec![0.0; LEN],
+            re: vec![0.0; LEN],
+            im: vec![0.0; LEN],
+        }
+    }
+}
+
+pub fn ui_analyzer(ui: &mut Ui, analyzer: &mut Analyzer, tap: &Tap) {
+    tap.read(&mut analyzer.samples);
+    let sample_rate = tap.sample_rate.load(Ordering::Relaxed) as f32;
+
+    ui.horizontal(|ui| {
+        ui.label("Scope");
+        ui.add(
+            DragValue::new(&mut analyzer.span)
+                .range(1.0..=40.0)
+                .speed(0.1)
+                .suffix(" ms"),
+        );
+    });
+    let shown = ((analyzer.span / 1000.0 * sample_rate) as usize).clamp(2, LEN / 2);
+    scope(ui, triggered(&analyzer.samples, shown));
+
+    ui.label("Spectrum");
+    let window_sum = LEN as f32 / 2.0;
+    for (i, &sample) in analyzer.samples.iter().enumerate() {
+        // Hann window
+        let window = 0.5 - 0.5 * (2.0 * PI * i as f32 / LEN as f32).cos();
+        analyzer.re[i] = sample * window;
+        analyzer.im[i] = 0.0;
+    }
+    fft(&mut analyzer.re, &mut analyzer.im);
+    let levels: Vec<f32> = (0..LEN / 2)
+        .map(|bin| {
+            let amp = analyzer.re[bin].hypot(analyzer.im[bin]) * 2.0 / window_sum;
+            20.0 * amp.max(1e-6).log10()
+        })
+        .collect();
+    spectrum(ui, &levels, sample_rate);
+}
+
+/// The last `shown` samples starting at a rising zero crossing, so periodic
+/// tones stand still, or simply the last `shown` samples without one
+fn triggered(samples: &[f32], shown: usize) -> &[f32] {
+    let latest = samples.len() - shown;
+    let start = (1..=latest)
+        .rev()
+        .find(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0)
+        .unwrap_or(latest);
+    &samples[start..start + shown]
+}
+
+fn allocate(ui: &mut Ui) -> Rect {
+    let width = ui.available_width().clamp(200.0, 600.0);
+    let (rect, _) = ui.allocate_exact_size(vec2(width, HEIGHT), Sense::hover());
+    ui.painter()
+        .rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    rect
+}
+
+fn scope(ui: &mut Ui, samples: &[f32]) {
+    let rect = allocate(ui);
+    let visuals = ui.visuals();
+    let painter = ui.painter_at(rect);
+    let grid = Stroke::new(1.0, visuals.widgets.noninteractive.bg_stroke.color);
+    painter.hline(rect.x_range(), rect.center().y, grid);
+    let line = samples
+        .iter()
+        .enumerate()
+        .map(|(i, &sample)| {
+            let x = i as f32 / (samples.len() - 1) as f32;
+            let y = 0.5 - sample.clamp(-1.0, 1.0) * 0.5;
+            rect.min + vec2(x, y) * rect.size()
+        })
+        .collect();
+    painter.line(line, Stroke::new(1.5, visuals.selection.stroke.color));
+}
+
+/// Draws `levels` in dB per FFT bin on a log-frequency axis
+fn spectrum(ui: &mut Ui, levels: &[f32], sample_rate: f32) {
+    let rect = allocate(ui);
+    let visuals = ui.visuals();
+    let painter = ui.painter_at(rect);
+    let nyquist = sample_rate / 2.0;
+    let to_screen = |hz: f32, db: f32| -> Pos2 {
+        let x = (hz / MIN_HZ).ln() / (nyquist / MIN_HZ).ln();
+        let y = db / MIN_DB;
+        rect.min + Vec2::new(x, y.clamp(0.0, 1.0)) * rect.size()
+    };
+
+    let grid = Stroke::new(1.0, visuals.widgets.noninteractive.bg_stroke.color);
+    let font = FontId::monospace(10.0);
+    let text = visuals.weak_text_color();
+    for (hz, label) in [(100.0, "100"), (1000.0, "1k"), (10000.0, "10k")] {
+        if hz < nyquist {
+            let x = to_screen(hz, 0.0).x;
+            painter.vline(x, rect.y_range(), grid);
+            painter.text(
+                pos2(x + 2.0, rect.bottom() - 2.0),
+                Align2::LEFT_BOTTOM,
+                label,
+                font.clone(),
+                text,
+            );
+        }
+    }
+    for db in (MIN_DB as i32..0).step_by(20).skip(1) {
+        let y = to_screen(MIN_HZ, db as f32).y;
+        painter.hline(rect.x_range(), y, grid);
+        painter.text(
+            pos2(rect.left() + 2.0, y),
+            Align2::LEFT_BOTTOM,
+            format!("{db} dB"),
+            font.clone(),
+            text,
+        );
+    }
+
+    let bin_hz = sample_rate / (2 * levels.len()) as f32;
+    let line = levels
+        .iter()
+        .enumerate()
+        .filter(|&(bin, _)| bin as f32 * bin_hz >= MIN_HZ)
+        .map(|(bin, &db)| to_screen(bin as f32 * bin_hz, db))
+        .collect();
+    painter.line(line, Stroke::new(1.5, visuals.selection.stroke.color));
+}
@@ -0,0 +1,301 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    process::ExitCode,
+};
+
+use timbre_synth::{
+    Combine, Control, Curve, CurveEnd, Filter, FilterMode, MAX_LENGTH, TimbreFile, Waveform,
+    render,
+    wav::{self, BitDepth},
+};
+
+const USAGE: &str = "\
+Usage:
+  timbre_tweak                        Open the editor
+  timbre_tweak render <TIMBRE> [OPTIONS]
+                                      Render a timbre to a WAV file or raw PCM
+  timbre_tweak info <TIMBRE>          Print a summary of a timbre
+  timbre_tweak validate <TIMBRE>...   Check that timbre files can be loaded
+  timbre_tweak help                   Print this message
+
+Render options:
+  -o, --output <PATH>     Output file, `-` for stdout [default: -]
+  --raw                   Write headerless PCM even to a file (always used for stdout)
+  --hz <HZ>               Note frequency [default: 440]
+  --hold <SECONDS>        Time the note is held before its release, up to 3600 [default: 1]
+  --sample-rate <HZ>      Up to 192000 [default: 44100]
+  --bit-depth <DEPTH>     16, 24, 32 or 32f [default: 16]
+";
+
+/// Highest `--sample-rate`, as in the editor
+const MAX_SAMPLE_RATE: u32 = 192000;
+
+/// Runs the command line interface with the arguments following the program name
+pub fn run(args: &[String]) -> ExitCode {
+    let result = match args[0].as_str() {
+        "render" => render_cmd(&args[1..]),
+        "info" => info(&args[1..]),
+        "validate" => validate(&args[1..]),
+        "help" | "-h" | "--help" => {
+            print!("{USAGE}");
+            Ok(())
+        }
+        command => Err(format!("Unknown command '{command}'\n\n{USAGE}")),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn load(path: &str) -> Result<TimbreFile, String> {
+    TimbreFile::load(path).map_err(|err| format!("{path}: {err}"))
+}
+
+/// Formats seconds since the Unix epoch as a UTC date and time
+pub fn format_time(secs: u64) -> String {
+    // Days to civil date, from Howard Hinnant's date algorithms
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!(
+        "{year}-{month:02}-{day:02} {:02}:{:02} UTC",
+        secs % 86400 / 3600,
+        secs % 3600 / 60
+    )
+}
+
+fn render_cmd(args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut output = "-".to_owned();
+    let mut raw = false;
+    let mut hz: f32 = 440.0;
+    let mut hold = 1.0;
+    let mut sample_rate = 44100;
+    let mut bit_depth = BitDepth::Int16;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("Missing value for '{arg}'"))
+        };
+        match arg.as_str() {
+            "-o" | "--output" => output = value()?.clone(),
+            "--raw" => raw = true,
+            "--hz" => hz = parse(arg, value()?)?,
+            "--hold" => hold = parse(arg, value()?)?,
+            "--sample-rate" => sample_rate = parse(arg, value()?)?,
+            "--bit-depth" => {
+                bit_depth = match value()?.as_str() {
+                    "16" => BitDepth::Int16,
+                    "24" => BitDepth::Int24,
+                    "32" => BitDepth::Int32,
+                    "32f" => BitDepth::Float32,
+                    depth => return Err(format!("Invalid bit depth '{depth}'")),
+                }
+            }
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => return Err(format!("Unexpected argument '{arg}'")),
+        }
+    }
+    let path = path.ok_or("Missing timbre file")?;
+    if !(hz.is_finite() && hz > 0.0) {
+        return Err("Frequency must be positive".to_owned());
+    }
+    if !(0.0..=MAX_LENGTH).contains(&hold) {
+        return Err(format!("Hold must be between 0 and {MAX_LENGTH} seconds"));
+    }
+    if !(1..=MAX_SAMPLE_RATE).contains(&sample_rate) {
+        return Err(format!(
+            "Sample rate must be between 1 and {MAX_SAMPLE_RATE} Hz"
+        ));
+    }
+
+    let timbre = load(path)?.timbre;
+    let samples = render(&timbre, hz, hold, sample_rate);
+    let result = if output == "-" {
+        let mut writer = BufWriter::new(io::stdout().lock());
+        wav::write_pcm(&mut writer, &samples, bit_depth).and_then(|()| writer.flush())
+    } else {
+        File::create(&output).and_then(|file| {
+            let mut writer = BufWriter::new(file);
+            if raw {
+                wav::write_pcm(&mut writer, &samples, bit_depth)?;
+                writer.flush()
+            } else {
+                wav::write(writer, &samples, sample_rate, bit_depth)
+            }
+        })
+    };
+    result.map_err(|err| format!("{output}: {err}"))
+}
+
+fn parse<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value '{value}' for '{arg}'"))
+}
+
+fn info(args: &[String]) -> Result<(), String> {
+    let [path] = args else {
+        return Err(format!("Expected exactly one timbre file\n\n{USAGE}"));
+    };
+    let TimbreFile { metadata, timbre } = load(path)?;
+    if !metadata.name.is_empty() {
+        println!("Name: {}", metadata.name);
+    }
+    if !metadata.author.is_empty() {
+        println!("Author: {}", metadata.author);
+    }
+    if !metadata.tags.is_empty() {
+        println!("Tags: {}", metadata.tags.join(", "));
+    }
+    if let Some(created) = metadata.created {
+        println!("Created: {}", format_time(created));
+    }
+    if let Some(modified) = metadata.modified {
+        println!("Modified: {}", format_time(modified));
+    }
+    println!("Global volume: {}", control_info(&timbre.amp));
+    match timbre.sustain {
+        Some(sustain) => println!("Sustain loop: {} s to {} s", sustain.start, sustain.end),
+        None => println!("Sustain loop: none"),
+    }
+    if let Some(filter) = &timbre.filter {
+        print_filter("Global filter", filter, "");
+    }
+    println!("Length: {} s", timbre.length);
+    println!("Waves: {}", timbre.waves.len());
+    for (i, wave) in timbre.waves.iter().enumerate() {
+        let waveform = match wave.waveform {
+            Waveform::Sine => "sine",
+            Waveform::Triangle => "triangle",
+            Waveform::Sawtooth => "sawtooth",
+            Waveform::Square => "square",
+            Waveform::WhiteNoise => "white noise",
+            Waveform::Table(_) => "wavetable",
+        };
+        let bandlimited = if wave.bandlimited {
+            "band-limited "
+        } else {
+            ""
+        };
+        println!("  {}: {bandlimited}{waveform}", i + 1);
+        println!("     volume: {}", control_info(&wave.amp));
+        println!("     relative frequency: {}", curve_info(&wave.freq));
+        match &wave.waveform {
+            Waveform::Square => println!("     pulse width: {}", curve_info(&wave.width)),
+            Waveform::Table(table) if table.frames() > 1 => {
+                println!("     frames: {}", table.frames());
+                println!("     position: {}", curve_info(&wave.position));
+            }
+            Waveform::Triangle => println!("     symmetry: {}", curve_info(&wave.width)),
+            _ => {}
+        }
+        if let Some(filter) = &wave.filter {
+            print_filter("filter", filter, "     ");
+        }
+        if let Some(modulation) = &wave.modulates {
+            println!(
+                "     modulates wave {} with index {}",
+                modulation.target + 1,
+                curve_info(&modulation.index)
+            );
+        }
+        if wave.feedback != 0.0 {
+            println!("     feedback: {}", wave.feedback);
+        }
+        let source = |source: Option<usize>| match source {
+            Some(source) => format!("wave {}", source + 1),
+            None => "the preceding wave".to_owned(),
+        };
+        match wave.combine {
+            Combine::Add => {}
+            Combine::Ring { source: from } => println!("     ring modulates {}", source(from)),
+            Combine::Am {
+                source: from,
+                depth,
+            } => println!(
+                "     amplitude modulates {} with depth {depth}",
+                source(from)
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn print_filter(label: &str, filter: &Filter, indent: &str) {
+    let mode = match filter.mode {
+        FilterMode::LowPass => "low-pass",
+        FilterMode::HighPass => "high-pass",
+        FilterMode::BandPass => "band-pass",
+        FilterMode::Notch => "notch",
+    };
+    println!(
+        "{indent}{label}: {mode}, key tracking {}",
+        filter.key_tracking
+    );
+    println!("{indent}  cutoff: {}", control_info(&filter.cutoff));
+    println!("{indent}  resonance: {}", control_info(&filter.resonance));
+}
+
+fn control_info(control: &Control) -> String {
+    match control {
+        Control::Envelope(envelope) => format!(
+            "envelope with delay {} s, attack {} s, hold {} s, decay {} s, sustain {}, release {} s",
+            envelope.delay,
+            envelope.attack,
+            envelope.hold,
+            envelope.decay,
+            envelope.sustain,
+            envelope.release
+        ),
+        Control::Curve(curve) => curve_info(curve),
+    }
+}
+
+fn curve_info(curve: &Curve) -> String {
+    let end = match curve.end {
+        CurveEnd::Hold => "hold",
+        CurveEnd::Loop => "loop",
+        CurveEnd::PingPong => "ping-pong",
+    };
+    let points: Vec<_> = curve
+        .points
+        .iter()
+        .map(|point| format!("{} s: {}", point.time, point.value))
+        .collect();
+    format!("[{}], then {end}", points.join(", "))
+}
+
+fn validate(args: &[String]) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(format!("Expected at least one timbre file\n\n{USAGE}"));
+    }
+    let mut invalid = 0;
+    for path in args {
+        match load(path) {
+            Ok(_) => println!("{path}: ok"),
+            Err(err) => {
+                println!("{err}");
+                invalid += 1;
+            }
+        }
+    }
+    if invalid == 0 {
+        Ok(())
+    } else {
+        Err(format!("{invalid} of {} files are invalid", args.len()))
+    }
+}
@@ -0,0 +1,323 @@
+//! Interactive plot for editing the breakpoints of a [`Curve`]
+
+use std::mem;
+
+use eframe::egui::{
+    Align2, DragValue, FontId, Id, Pos2, Rect, Response, Sense, Stroke, Ui, pos2, vec2,
+};
+use timbre_synth::{Curve, Point, Shape};
+
+const HEIGHT: f32 = 100.0;
+/// Distance within which the pointer grabs a point
+const GRAB_RADIUS: f32 = 6.0;
+
+/// View and interaction state kept in egui memory between frames
+#[derive(Clone, Default)]
+struct State {
+    /// Visible seconds, or 0.0 to fit the curve
+    span: f32,
+    /// Largest visible value, frozen while dragging so the view does not move
+    value_max: f32,
+    snap: bool,
+    dragging: Option<usize>,
+    /// Point whose context menu is open
+    menu: Option<usize>,
+}
+
+/// Maps between curve coordinates and screen positions
+struct View {
+    rect: Rect,
+    span: f32,
+    value_max: f32,
+}
+
+impl View {
+    fn to_screen(&self, time: f32, value: f32) -> Pos2 {
+        pos2(
+            self.rect.left() + time / self.span * self.rect.width(),
+            self.rect.bottom() - value / self.value_max * self.rect.height(),
+        )
+    }
+
+    fn to_curve(&self, pos: Pos2) -> (f32, f32) {
+        let time = (pos.x - self.rect.left()) / self.rect.width() * self.span;
+        let value = (self.rect.bottom() - pos.y) / self.rect.height() * self.value_max;
+        (time.max(0.0), value.max(0.0))
+    }
+
+    /// Index of the point under `pos`
+    fn grab(&self, curve: &Curve, pos: Pos2) -> Option<usize> {
+        curve
+            .points
+            .iter()
+            .map(|point| self.to_screen(point.time, point.value).distance(pos))
+            .enumerate()
+            .filter(|&(_, distance)| distance < GRAB_RADIUS)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i)
+    }
+}
+
+/// Round step of about a tenth of `range`, for grid lines and snapping
+fn grid_step(range: f32) -> f32 {
+    let rough = range / 10.0;
+    let magnitude = 10f32.powf(rough.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|factor| factor * magnitude)
+        .find(|&step| step >= rough)
+        .unwrap_or(magnitude * 10.0)
+}
+
+fn snap(x: f32, step: f32) -> f32 {
+    (x / step).round() * step
+}
+
+/// Shows `curve` as a plot where points can be dragged, added by clicking,
+/// and changed or deleted from their context menu
+pub fn curve_editor(ui: &mut Ui, id: Id, curve: &mut Curve) -> Response {
+    let mut state: State = ui.data(|data| data.get_temp(id)).unwrap_or_default();
+
+    ui.horizontal(|ui| {
+        ui.checkbox(&mut state.snap, "Snap");
+        if ui.button("Fit").clicked() {
+            state.span = 0.0;
+        }
+    });
+
+    let width = ui.available_width().clamp(200.0, 600.0);
+    let (rect, response) = ui.allocate_exact_size(vec2(width, HEIGHT), Sense::click_and_drag());
+    if response.hovered() {
+        let zoom = ui.input(|input| input.zoom_delta());
+        if zoom != 1.0 {
+            state.span = (state.span_or_fit(curve) / zoom).clamp(0.01, 600.0);
+        }
+    }
+    // Undo or the point menu may have removed the point since the drag started
+    if state.dragging.is_some_and(|i| i >= curve.points.len()) {
+        state.dragging = None;
+    }
+    if state.dragging.is_none() {
+        state.value_max = curve
+            .points
+            .iter()
+            .map(|point| point.value * 1.25)
+            .fold(1.0, f32::max);
+    }
+    let view = View {
+        rect,
+        span: state.span_or_fit(curve),
+        value_max: state.value_max,
+    };
+    let (time_step, value_step) = (grid_step(view.span), grid_step(view.value_max));
+
+    let pointer = response.interact_pointer_pos().or(response.hover_pos());
+    let hovered = pointer.and_then(|pos| view.grab(curve, pos));
+    if response.drag_started() {
+        state.dragging = hovered;
+    }
+    if let Some(i) = state.dragging
+        && let Some(pos) = pointer
+        && response.dragged()
+    {
+        let (mut time, mut value) = view.to_curve(pos);
+        if state.snap {
+            (time, value) = (snap(time, time_step), snap(value, value_step));
+        }
+        // Keep the points sorted by time
+        let min = if i == 0 {
+            0.0
+        } else {
+            curve.points[i - 1].time
+        };
+        let max = curve
+            .points
+            .get(i + 1)
+            .map_or(f32::INFINITY, |next| next.time);
+        curve.points[i].time = time.clamp(min, max);
+        curve.points[i].value = value;
+    }
+    if response.drag_stopped() {
+        state.dragging = None;
+    }
+    if response.clicked()
+        && hovered.is_none()
+        && let Some(pos) = pointer
+    {
+        let (mut time, mut value) = view.to_curve(pos);
+        if state.snap {
+            (time, value) = (snap(time, time_step), snap(value, value_step));
+        }
+        let i = curve.points.partition_point(|point| point.time <= time);
+        let shape = curve.points[i.saturating_sub(1)].shape;
+        curve.points.insert(i, Point { time, value, shape });
+    }
+    if response.secondary_clicked() {
+        state.menu = hovered;
+    }
+
+    paint(
+        ui,
+        &view,
+        curve,
+        (time_step, value_step),
+        hovered.or(state.dragging),
+    );
+
+    let menu = state.menu;
+    ui.data_mut(|data| data.insert_temp(id, state));
+    response.context_menu(|ui| match menu {
+        Some(i) if i < curve.points.len() => point_menu(ui, curve, i),
+        _ => {
+            ui.label("Right-click a point to edit it");
+        }
+    });
+    response.on_hover_text(
+        "Click to add a point, drag to move it, right-click to edit it, Ctrl+scroll to zoom",
+    )
+}
+
+impl State {
+    fn span_or_fit(&self, curve: &Curve) -> f32 {
+        if self.span > 0.0 {
+            self.span
+        } else {
+            (curve.duration() * 1.25).max(1.0)
+        }
+    }
+}
+
+fn paint(
+    ui: &Ui,
+    view: &View,
+    curve: &Curve,
+    (time_step, value_step): (f32, f32),
+    hot: Option<usize>,
+) {
+    let visuals = ui.visuals();
+    let painter = ui.painter_at(view.rect);
+    painter.rect_filled(view.rect, 2.0, visuals.extreme_bg_color);
+
+    let grid = Stroke::new(1.0, visuals.widgets.noninteractive.bg_stroke.color);
+    let mut time = 0.0;
+    while time <= view.span {
+        painter.vline(view.to_screen(time, 0.0).x, view.rect.y_range(), grid);
+        time += time_step;
+    }
+    let mut value = 0.0;
+    while value <= view.value_max {
+        painter.hline(view.rect.x_range(), view.to_screen(0.0, value).y, grid);
+        value += value_step;
+    }
+    let end = view.to_screen(curve.duration(), 0.0).x;
+    painter.vline(
+        end,
+        view.rect.y_range(),
+        Stroke::new(1.0, visuals.weak_text_color()),
+    );
+
+    // Sample what the synth actually plays, one point per pixel
+    let width = view.rect.width() as usize;
+    let line = (0..=width)
+        .map(|x| {
+            let time = x as f32 / width as f32 * view.span;
+            view.to_screen(time, curve.at(time))
+        })
+        .collect();
+    let accent = visuals.selection.stroke.color;
+    painter.line(line, Stroke::new(1.5, accent));
+
+    for (i, point) in curve.points.iter().enumerate() {
+        let color = if Some(i) == hot {
+            visuals.strong_text_color()
+        } else {
+            accent
+        };
+        painter.circle_filled(view.to_screen(point.time, point.value), 4.0, color);
+    }
+
+    let font = FontId::monospace(10.0);
+    let text = visuals.weak_text_color();
+    painter.text(
+        view.rect.left_top() + vec2(2.0, 2.0),
+        Align2::LEFT_TOP,
+        format!("{:.2}", view.value_max),
+        font.clone(),
+        text,
+    );
+    painter.text(
+        view.rect.right_bottom() - vec2(2.0, 2.0),
+        Align2::RIGHT_BOTTOM,
+        format!("{:.2} s", view.span),
+        font.clone(),
+        text,
+    );
+    if let Some(point) = hot.and_then(|i| curve.points.get(i)) {
+        painter.text(
+            view.rect.right_top() + vec2(-2.0, 2.0),
+            Align2::RIGHT_TOP,
+            format!("{:.3} s: {:.3}", point.time, point.value),
+            font,
+            visuals.text_color(),
+        );
+    }
+}
+
+fn point_menu(ui: &mut Ui, curve: &mut Curve, i: usize) {
+    let min = if i == 0 {
+        0.0
+    } else {
+        curve.points[i - 1].time
+    };
+    let max = curve
+        .points
+        .get(i + 1)
+        .map_or(f32::INFINITY, |next| next.time);
+    let has_segment = i + 1 < curve.points.len();
+    let point = &mut curve.points[i];
+    ui.add(
+        DragValue::new(&mut point.time)
+            .range(min..=max)
+            .speed(0.01)
+            .prefix("Time ")
+            .suffix(" s"),
+    );
+    ui.add(
+        DragValue::new(&mut point.value)
+            .range(0.0..=f32::INFINITY)
+            .speed(0.01)
+            .prefix("Value "),
+    );
+    if has_segment {
+        ui.separator();
+        ui_shape(ui, &mut point.shape);
+    }
+    ui.separator();
+    if ui.button("Delete").clicked() && curve.points.len() > 1 {
+        curve.points.remove(i);
+        ui.close();
+    }
+}
+
+fn ui_shape(ui: &mut Ui, shape: &mut Shape) {
+    for (option, name) in [
+        (Shape::Linear, "Linear"),
+        (Shape::Exponential { curvature: 4.0 }, "Exponential"),
+        (Shape::Step, "Step"),
+        (Shape::Smooth, "Smooth"),
+    ] {
+        // Keep the curvature when reselecting
+        let selected = mem::discriminant(shape) == mem::discriminant(&option);
+        if ui.selectable_label(selected, name).clicked() && !selected {
+            *shape = option;
+        }
+    }
+    if let Shape::Exponential { curvature } = shape {
+        ui.add(
+            DragValue::new(curvature)
+                .range(-20.0..=20.0)
+                .speed(0.1)
+                .prefix("Curvature "),
+        );
+    }
+}
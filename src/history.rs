@@ -0,0 +1,107 @@
+//! Undo and redo of timbre edits
+
+use std::{collections::VecDeque, mem};
+
+use eframe::egui::{Button, Context, Key, KeyboardShortcut, Modifiers, Ui};
+use timbre_synth::Timbre;
+
+/// Number of edits that can be undone
+const LIMIT: usize = 100;
+
+const UNDO: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Z);
+const REDO: KeyboardShortcut =
+    KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::Z);
+const REDO_Y: KeyboardShortcut = KeyboardShortcut::new(Modifiers::COMMAND, Key::Y);
+
+/// Snapshots of the timbre before and after the recorded edits
+pub struct History {
+    undo: VecDeque<Timbre>,
+    redo: Vec<Timbre>,
+    /// Timbre after the last recorded edit
+    current: Timbre,
+}
+
+impl History {
+    pub fn new(timbre: Timbre) -> Self {
+        Self {
+            undo: VecDeque::new(),
+            redo: vec![],
+            current: timbre,
+        }
+    }
+
+    /// Records the changes to `timbre` as one edit. Call this every frame;
+    /// while `editing`, changes are collected into the same edit instead.
+    pub fn record(&mut self, timbre: &Timbre, editing: bool) {
+        if editing || *timbre == self.current {
+            return;
+        }
+        self.undo
+            .push_back(mem::replace(&mut self.current, timbre.clone()));
+        if self.undo.len() > LIMIT {
+            self.undo.pop_front();
+        }
+        self.redo.clear();
+    }
+
+    fn can_undo(&self, timbre: &Timbre) -> bool {
+        !self.undo.is_empty() || *timbre != self.current
+    }
+
+    fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    pub fn undo(&mut self, timbre: &mut Timbre) {
+        self.record(timbre, false);
+        if let Some(previous) = self.undo.pop_back() {
+            self.redo.push(mem::replace(&mut self.current, previous));
+            *timbre = self.current.clone();
+        }
+    }
+
+    pub fn redo(&mut self, timbre: &mut Timbre) {
+        // An unrecorded edit clears the redo stack
+        self.record(timbre, false);
+        if let Some(next) = self.redo.pop() {
+            self.undo.push_back(mem::replace(&mut self.current, next));
+            *timbre = self.current.clone();
+        }
+    }
+}
+
+/// Whether an edit is in progress that should become a single history entry,
+/// like dragging a value or typing into a field
+pub fn is_editing(ctx: &Context) -> bool {
+    ctx.input(|input| input.pointer.any_down()) || ctx.wants_keyboard_input()
+}
+
+/// Handles the undo and redo shortcuts, leaving them to text fields while typing
+pub fn shortcuts(ctx: &Context, history: &mut History, timbre: &mut Timbre) {
+    if ctx.wants_keyboard_input() {
+        return;
+    }
+    // Check redo first, since the undo shortcut also matches with Shift held
+    if ctx.input_mut(|input| input.consume_shortcut(&REDO) || input.consume_shortcut(&REDO_Y)) {
+        history.redo(timbre);
+    } else if ctx.input_mut(|input| input.consume_shortcut(&UNDO)) {
+        history.undo(timbre);
+    }
+}
+
+pub fn ui_history(ui: &mut Ui, history: &mut History, timbre: &mut Timbre) {
+    let undo = ui.add_enabled(history.can_undo(timbre), Button::new("Undo"));
+    if undo
+        .on_hover_text(ui.ctx().format_shortcut(&UNDO))
+        .clicked()
+    {
+        history.undo(timbre);
+    }
+    let redo = ui.add_enabled(history.can_redo(), Button::new("Redo"));
+    if redo
+        .on_hover_text(ui.ctx().format_shortcut(&REDO))
+        .clicked()
+    {
+        history.redo(timbre);
+    }
+}
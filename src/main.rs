@@ -1,218 +1,782 @@
-use std::{env, f32::consts::TAU, fs, sync::Arc, thread};
+mod analyzer;
+mod cli;
+mod curve_editor;
+mod history;
+mod midi;
+mod output;
+mod toast;
+mod wavetable_editor;
 
+use std::{
+    env,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::Path,
+    process::ExitCode,
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, Sender},
+    },
+    thread,
+    time::Duration,
+};
+
+use analyzer::{Analyzer, Tap};
 use cpal::{
-    Device, FromSample, I24, SizedSample, Stream, StreamConfig,
-    traits::{DeviceTrait, HostTrait, StreamTrait},
+    BufferSize, Device, FromSample, I24, SizedSample, Stream, StreamConfig,
+    traits::{DeviceTrait, StreamTrait},
 };
+use curve_editor::curve_editor;
 use eframe::{
     App, NativeOptions,
     egui::{
-        CentralPanel, Context, DragValue, Popup, PopupCloseBehavior, ScrollArea, Slider, Ui,
-        mutex::Mutex,
+        Button, CentralPanel, ComboBox, Context, DragValue, Id, Popup, PopupCloseBehavior,
+        ScrollArea, SidePanel, Slider, TextEdit, Ui, mutex::Mutex,
     },
 };
+use history::History;
+use midi::Midi;
+use output::Output;
 use rfd::FileDialog;
-use serde::{Deserialize, Serialize};
+use timbre_synth::{
+    Combine, Control, Curve, CurveEnd, Envelope, Filter, FilterMode, MAX_LENGTH, Metadata,
+    Modulation, StealPolicy, Sustain, Synth, Timbre, TimbreFile, Wave, Waveform, Wavetable, render,
+    wav::{self, BitDepth},
+};
+use toast::Toasts;
+use wavetable_editor::wavetable_editor;
 
-fn main() {
-    let app = MyApp(Arc::new(Mutex::new(Playback {
-        stream_config: None,
-        stream: None,
-        sample: 0,
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        return cli::run(&args);
+    }
+
+    let timbre = Timbre {
+        amp: Curve::constant(0.5).into(),
+        waves: vec![],
+        filter: None,
+        sustain: Some(Sustain {
+            start: 0.0,
+            end: 1.0,
+        }),
+        length: 1.0,
+    };
+    let controls = Controls {
         hz: 440.0,
-        timbre: Timbre {
-            amp: Curve(vec![0.5]),
-            waves: vec![],
+        hold: true,
+        a4: 440.0,
+        max_voices: 8,
+        steal: StealPolicy::Oldest,
+    };
+    let settings = output::Settings::load();
+    let tap = Arc::new(Tap::new());
+    let audio = Arc::new(Mutex::new(Audio {
+        stream: None,
+        commands: None,
+        old_timbres: None,
+        tap: tap.clone(),
+        settings: settings.clone(),
+        status: Status::NoDevice("Not started".to_owned()),
+        controls: controls.clone(),
+        timbre: timbre.clone(),
+    }));
+    setup_audio(audio.clone());
+    let app = MyApp {
+        audio,
+        tap,
+        analyzer: Analyzer::default(),
+        controls,
+        midi: Midi::default(),
+        output: Output::new(settings),
+        metadata: Metadata::default(),
+        history: History::new(timbre.clone()),
+        timbre,
+        export: Export {
+            hold: 1.0,
+            sample_rate: 44100,
+            bit_depth: BitDepth::Int16,
         },
-    })));
-    setup_audio(app.clone());
+        toasts: Toasts::default(),
+    };
     eframe::run_native(
         "Timbre Tweak",
         NativeOptions::default(),
         Box::new(|_| Ok(Box::new(app))),
     )
     .expect("Error running eframe App");
+    ExitCode::SUCCESS
 }
 
-#[derive(PartialEq, Serialize, Deserialize)]
-enum Waveform {
-    Sine,
-    Triangle,
-    Sawtooth,
-    Square,
-    WhiteNoise,
-}
-
-impl Waveform {
-    fn at(&self, t: f32) -> f32 {
-        match *self {
-            Self::Sine => (t * TAU).sin(),
-            Self::Triangle => {
-                if t.fract() < 0.5 {
-                    t.fract() * 4.0 - 1.0
-                } else {
-                    3.0 - t.fract() * 4.0
-                }
-            }
-            Self::Sawtooth => t.fract() * 2.0 - 1.0,
-            Self::Square => {
-                if t.fract() < 0.5 {
-                    -1.0
-                } else {
-                    1.0
-                }
-            }
-            Self::WhiteNoise => {
-                let mut n = t.to_bits();
-                // fmix32 (MurmurHash3)
-                n = (n ^ n >> 16).wrapping_mul(0x85EBCA6B);
-                n = (n ^ n >> 13).wrapping_mul(0xC2B2AE35);
-                n ^= n >> 16;
-                n as f32 / i32::MAX as f32 - 1.0
-            }
-        }
-    }
+/// Key of the note played with the hz slider, outside the range of MIDI notes
+const SLIDER_KEY: u32 = 128;
+
+/// Messages sent from the GUI and MIDI input to the audio thread
+enum Command {
+    NoteOn {
+        key: u32,
+        hz: f32,
+        velocity: f32,
+    },
+    NoteOff {
+        key: u32,
+    },
+    SetNoteHz {
+        key: u32,
+        hz: f32,
+    },
+    SetPolyphony {
+        max_voices: usize,
+        steal: StealPolicy,
+    },
+    SetTimbre(Box<Timbre>),
 }
 
-/// A linearly-interpolated curve in range 0.0..1.0
-#[derive(Serialize, Deserialize)]
-struct Curve(Vec<f32>);
+/// Playback settings edited in the GUI
+#[derive(Clone, PartialEq)]
+struct Controls {
+    /// Frequency of the slider note
+    hz: f32,
+    /// Whether the slider note is held
+    hold: bool,
+    /// Tuning reference of MIDI notes
+    a4: f32,
+    max_voices: usize,
+    steal: StealPolicy,
+}
 
-impl Curve {
-    fn at(&self, t: f32) -> f32 {
-        let i = t * (self.0.len() - 1) as f32;
-        let (fract, i) = (i.fract(), i as usize);
-        if i == self.0.len() - 1 {
-            self.0[i]
-        } else {
-            (1.0 - fract) * self.0[i] + fract * self.0[i + 1]
+/// Audio output shared between the GUI and the stream error callback.
+/// The audio callback itself never locks this.
+struct Audio {
+    stream: Option<Stream>,
+    commands: Option<Sender<Command>>,
+    /// Timbres replaced on the audio thread, to be freed here instead
+    old_timbres: Option<Receiver<Box<Timbre>>>,
+    /// Output samples for the analyzer, shared with the audio thread
+    tap: Arc<Tap>,
+    /// Output the stream was opened with
+    settings: output::Settings,
+    status: Status,
+    /// Last controls sent to the audio thread
+    controls: Controls,
+    /// Last timbre sent to the audio thread
+    timbre: Timbre,
+}
+
+impl Audio {
+    fn send(&self, command: Command) {
+        if let Some(commands) = &self.commands {
+            // The receiver is gone only while the stream is being rebuilt,
+            // which picks up `controls` and `timbre` anyway
+            let _ = commands.send(command);
+        }
+    }
+
+    fn sync(&mut self, controls: &Controls, timbre: &Timbre) {
+        let old = &self.controls;
+        if old.hz != controls.hz {
+            self.send(Command::SetNoteHz {
+                key: SLIDER_KEY,
+                hz: controls.hz,
+            });
+        }
+        if old.hold != controls.hold {
+            self.send(if controls.hold {
+                Command::NoteOn {
+                    key: SLIDER_KEY,
+                    hz: controls.hz,
+                    velocity: 1.0,
+                }
+            } else {
+                Command::NoteOff { key: SLIDER_KEY }
+            });
+        }
+        if (old.max_voices, old.steal) != (controls.max_voices, controls.steal) {
+            self.send(Command::SetPolyphony {
+                max_voices: controls.max_voices,
+                steal: controls.steal,
+            });
+        }
+        self.controls = controls.clone();
+        if let Some(old_timbres) = &self.old_timbres {
+            while old_timbres.try_recv().is_ok() {}
+        }
+        if self.timbre != *timbre {
+            self.timbre = timbre.clone();
+            self.send(Command::SetTimbre(Box::new(timbre.clone())));
         }
     }
 }
 
-#[derive(Serialize, Deserialize)]
-struct Wave {
-    waveform: Waveform,
-    freq: Curve,
-    amp: Curve,
+/// State of the audio output shown in the GUI.
+/// Without a device, everything but playback keeps working.
+#[derive(Clone)]
+enum Status {
+    /// Description of the device and stream config
+    Playing(String),
+    /// The stream failed with this error and is being rebuilt
+    Reconnecting(String),
+    /// No stream could be opened, for this reason
+    NoDevice(String),
 }
 
-impl Wave {
-    fn at(&self, sec: f32, hz: f32) -> f32 {
-        self.waveform.at(sec * hz * self.freq.at(sec)) * self.amp.at(sec)
-    }
+/// State owned by the audio thread
+struct Playback {
+    commands: Receiver<Command>,
+    /// Returns replaced timbres, so the audio thread does not free them
+    old_timbres: Sender<Box<Timbre>>,
+    channels: usize,
+    synth: Synth,
+    tap: Arc<Tap>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct Timbre {
-    amp: Curve,
-    waves: Vec<Wave>,
+/// Settings of the "Export WAV" action
+struct Export {
+    /// Time the note is held before its release
+    hold: f32,
+    sample_rate: u32,
+    bit_depth: BitDepth,
 }
 
-struct Playback {
-    stream_config: Option<StreamConfig>,
-    stream: Option<Stream>,
-    sample: u32,
-    hz: f32,
+struct MyApp {
+    audio: Arc<Mutex<Audio>>,
+    tap: Arc<Tap>,
+    analyzer: Analyzer,
+    controls: Controls,
+    midi: Midi,
+    output: Output,
+    metadata: Metadata,
+    history: History,
     timbre: Timbre,
+    export: Export,
+    toasts: Toasts,
 }
 
-#[derive(Clone)]
-struct MyApp(Arc<Mutex<Playback>>);
+impl MyApp {
+    fn save(&mut self, path: &Path) {
+        let mut metadata = self.metadata.clone();
+        metadata.tags.retain(|tag| !tag.is_empty());
+        metadata.touch();
+        let file = TimbreFile {
+            metadata,
+            timbre: self.timbre.clone(),
+        };
+        match file.save(path) {
+            Ok(()) => {
+                self.metadata = file.metadata;
+                self.toasts.info(format!("Saved {}", path.display()));
+            }
+            Err(err) => self
+                .toasts
+                .error(format!("Could not save {}: {err}", path.display())),
+        }
+    }
+
+    fn load(&mut self, path: &Path) {
+        match TimbreFile::load(path) {
+            Ok(file) => {
+                self.metadata = file.metadata;
+                self.timbre = file.timbre;
+            }
+            Err(err) => self
+                .toasts
+                .error(format!("Could not load {}: {err}", path.display())),
+        }
+    }
+}
 
 impl App for MyApp {
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
-        // TODO: don't block audio thread
-        let mut playback = self.0.lock();
+        SidePanel::right("analyzer").show(ctx, |ui| {
+            analyzer::ui_analyzer(ui, &mut self.analyzer, &self.tap);
+        });
+        history::shortcuts(ctx, &mut self.history, &mut self.timbre);
+        self.toasts.show(ctx);
+        // Keep the analyzer moving
+        ctx.request_repaint_after(Duration::from_millis(30));
         CentralPanel::default().show(ctx, |ui| {
-            ui.add(Slider::new(&mut playback.hz, 20.0..=2000.0).text("hz"));
+            ui_controls(ui, &mut self.controls);
+            midi::ui_midi(ui, &mut self.midi, &mut self.controls.a4, &self.audio);
+            output::ui_output(ui, &mut self.output, &self.audio);
+            ui.horizontal(|ui| {
+                history::ui_history(ui, &mut self.history, &mut self.timbre);
+            });
             if ui.button("Add wave").clicked() {
-                playback.timbre.waves.push(Wave {
+                self.timbre.waves.push(Wave {
                     waveform: Waveform::Sine,
-                    freq: Curve(vec![1.0]),
-                    amp: Curve(vec![0.5]),
+                    bandlimited: true,
+                    freq: Curve::constant(1.0),
+                    width: Curve::constant(0.5),
+                    position: Curve::constant(0.0),
+                    amp: Curve::constant(0.5).into(),
+                    filter: None,
+                    modulates: None,
+                    feedback: 0.0,
+                    combine: Combine::Add,
                 });
             }
             ui.add_space(25.0);
-            if ui.button("Save").clicked() {
-                if let Some(path) = file_dialog().save_file()
-                    && let Ok(str) = serde_json::to_string(&playback.timbre)
-                    && let Ok(()) = fs::write(path, str)
-                {
-                } else {
-                    eprintln!("File save failed");
-                }
+            ui_metadata(ui, &mut self.metadata);
+            let save = ui
+                .add_enabled(self.timbre.validate().is_ok(), Button::new("Save"))
+                .on_disabled_hover_text("Fix the timbre errors to save");
+            if save.clicked()
+                && let Some(path) = file_dialog("JSON", "json").save_file()
+            {
+                self.save(&path);
             }
-            if ui.button("Load").clicked() {
-                if let Some(path) = file_dialog().pick_file()
-                    && let Ok(slice) = fs::read(path)
-                    && let Ok(timbre) = serde_json::from_slice(&slice)
-                {
-                    playback.timbre = timbre;
-                } else {
-                    eprintln!("File load failed");
-                }
+            if ui.button("Load").clicked()
+                && let Some(path) = file_dialog("JSON", "json").pick_file()
+            {
+                self.load(&path);
             }
             ui.add_space(25.0);
-            ui_curve(ui, &mut playback.timbre.amp, "Global volume");
-            let mut i = 0;
-            let len = playback.timbre.waves.len();
+            ui_export(
+                ui,
+                &mut self.export,
+                self.controls.hz,
+                &self.timbre,
+                &mut self.toasts,
+            );
+            ui.add_space(25.0);
+            ui_control(ui, &mut self.timbre.amp, "Global volume");
+            ui_filter(ui, &mut self.timbre.filter, "Global filter");
+            ui_lifecycle(ui, &mut self.timbre);
+            if let Err(err) = self.timbre.validate() {
+                ui.colored_label(ui.visuals().error_fg_color, err);
+            }
+            let len = self.timbre.waves.len();
             let mut swap = vec![];
+            let mut remove = vec![];
             ScrollArea::vertical().show(ui, |ui| {
-                playback
-                    .timbre
-                    .waves
-                    .retain_mut(|wave| wave_ui(wave, &mut i, len, &mut swap, ui));
+                for (i, wave) in self.timbre.waves.iter_mut().enumerate() {
+                    if !wave_ui(wave, i, len, &mut swap, &mut self.toasts, ui) {
+                        remove.push(i);
+                    }
+                }
             });
+            for i in remove.into_iter().rev() {
+                self.timbre.remove_wave(i);
+            }
             for (i1, i2) in swap {
-                playback.timbre.waves.swap(i1, i2);
+                self.timbre.swap_waves(i1, i2);
             }
         });
+        self.history.record(&self.timbre, history::is_editing(ctx));
+        self.audio.lock().sync(&self.controls, &self.timbre);
     }
 }
 
-fn file_dialog() -> FileDialog {
+/// Reads a single-cycle or wavetable WAV file
+fn import_wavetable(path: &Path) -> Result<Wavetable, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let wav = wav::read(BufReader::new(file)).map_err(|err| err.to_string())?;
+    Wavetable::from_wav(&wav)
+}
+
+fn file_dialog(name: &str, extension: &str) -> FileDialog {
     FileDialog::new()
         .set_directory(env::current_dir().expect("Could not get current dir"))
-        .add_filter("JSON", &["json"])
+        .add_filter(name, &[extension])
 }
 
-fn ui_curve(ui: &mut Ui, curve: &mut Curve, label: &str) {
+fn ui_controls(ui: &mut Ui, controls: &mut Controls) {
+    ui.horizontal(|ui| {
+        ui.add(Slider::new(&mut controls.hz, 20.0..=2000.0).text("hz"));
+        ui.checkbox(&mut controls.hold, "Hold");
+        ui.add(
+            DragValue::new(&mut controls.max_voices)
+                .range(1..=64)
+                .suffix(" voices"),
+        );
+        ComboBox::from_id_salt("steal policy")
+            .selected_text(format!("Steal {}", steal_name(controls.steal)))
+            .show_ui(ui, |ui| {
+                for steal in [
+                    StealPolicy::Oldest,
+                    StealPolicy::Quietest,
+                    StealPolicy::SameNote,
+                ] {
+                    ui.selectable_value(&mut controls.steal, steal, steal_name(steal));
+                }
+            });
+    });
+}
+
+fn steal_name(steal: StealPolicy) -> &'static str {
+    match steal {
+        StealPolicy::Oldest => "oldest",
+        StealPolicy::Quietest => "quietest",
+        StealPolicy::SameNote => "same note",
+    }
+}
+
+fn ui_metadata(ui: &mut Ui, metadata: &mut Metadata) {
+    ui.horizontal(|ui| {
+        ui.label("Name");
+        ui.add(TextEdit::singleline(&mut metadata.name).desired_width(120.0));
+        ui.label("Author");
+        ui.add(TextEdit::singleline(&mut metadata.author).desired_width(120.0));
+        ui.label("Tags");
+        // Empty tags are kept while typing and dropped when saving
+        let mut tags = metadata.tags.join(", ");
+        if ui
+            .add(TextEdit::singleline(&mut tags).desired_width(160.0))
+            .changed()
+        {
+            metadata.tags = tags.split(',').map(|tag| tag.trim().to_owned()).collect();
+        }
+        if let Some(modified) = metadata.modified {
+            ui.weak(format!("Modified {}", cli::format_time(modified)));
+        }
+    });
+}
+
+fn ui_export(ui: &mut Ui, export: &mut Export, hz: f32, timbre: &Timbre, toasts: &mut Toasts) {
+    ui.horizontal(|ui| {
+        if ui.button("Export WAV").clicked()
+            && let Some(path) = file_dialog("WAV", "wav").save_file()
+        {
+            let result = File::create(&path).and_then(|file| {
+                wav::write(
+                    BufWriter::new(file),
+                    &render(timbre, hz, export.hold, export.sample_rate),
+                    export.sample_rate,
+                    export.bit_depth,
+                )
+            });
+            match result {
+                Ok(()) => toasts.info(format!("Exported {}", path.display())),
+                Err(err) => toasts.error(format!("Could not export {}: {err}", path.display())),
+            }
+        }
+        ui.add(
+            DragValue::new(&mut export.hold)
+                .prefix("Hold ")
+                .range(0.0..=600.0)
+                .speed(0.01)
+                .suffix(" s"),
+        );
+        ui.add(
+            DragValue::new(&mut export.sample_rate)
+                .range(8000..=192000)
+                .suffix(" Hz"),
+        );
+        ComboBox::from_id_salt("bit depth")
+            .selected_text(export.bit_depth.to_string())
+            .show_ui(ui, |ui| {
+                for bit_depth in BitDepth::ALL {
+                    ui.selectable_value(&mut export.bit_depth, bit_depth, bit_depth.to_string());
+                }
+            });
+    });
+}
+
+fn ui_lifecycle(ui: &mut Ui, timbre: &mut Timbre) {
+    ui.horizontal(|ui| {
+        let mut looping = timbre.sustain.is_some();
+        if ui.checkbox(&mut looping, "Sustain loop").changed() {
+            timbre.sustain = looping.then_some(Sustain {
+                start: 0.0,
+                end: timbre.length,
+            });
+        }
+        if let Some(sustain) = &mut timbre.sustain {
+            ui.add(
+                DragValue::new(&mut sustain.start)
+                    .range(0.0..=sustain.end)
+                    .speed(0.01)
+                    .suffix(" s"),
+            );
+            ui.add(
+                DragValue::new(&mut sustain.end)
+                    .range(sustain.start..=f32::INFINITY)
+                    .speed(0.01)
+                    .suffix(" s"),
+            );
+        }
+        ui.label("Length");
+        ui.add(
+            DragValue::new(&mut timbre.length)
+                .range(0.0..=MAX_LENGTH)
+                .speed(0.01)
+                .suffix(" s"),
+        );
+    });
+}
+
+fn ui_control(ui: &mut Ui, control: &mut Control, label: &str) {
+    let id = ui.make_persistent_id(label);
     ui.horizontal(|ui| {
         ui.label(label);
-        if ui.button("+").clicked() {
-            curve.0.push(*curve.0.last().unwrap());
+        let is_envelope = matches!(control, Control::Envelope(_));
+        ComboBox::from_id_salt(id.with("kind"))
+            .width(80.0)
+            .selected_text(if is_envelope { "Envelope" } else { "Curve" })
+            .show_ui(ui, |ui| {
+                if ui.selectable_label(!is_envelope, "Curve").clicked() && is_envelope {
+                    *control = Curve::constant(1.0).into();
+                }
+                if ui.selectable_label(is_envelope, "Envelope").clicked() && !is_envelope {
+                    *control = Control::Envelope(Envelope::default());
+                }
+            });
+        if let Control::Curve(curve) = control {
+            ui_curve_header(ui, id, curve);
+        }
+    });
+    match control {
+        Control::Envelope(envelope) => ui_envelope(ui, envelope),
+        Control::Curve(curve) => {
+            curve_editor(ui, id, curve);
+        }
+    }
+}
+
+fn ui_modulation(ui: &mut Ui, wave: &mut Wave, i: usize, len: usize) {
+    ui.horizontal(|ui| {
+        let mut target = wave.modulates.as_ref().map(|modulation| modulation.target);
+        let name = |target: Option<usize>| match target {
+            Some(target) => format!("Modulates wave {}", target + 1),
+            None => "Heard".to_owned(),
+        };
+        ComboBox::from_id_salt(ui.make_persistent_id("modulates"))
+            .selected_text(name(target))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut target, None, name(None));
+                for other in (0..len).filter(|&other| other != i) {
+                    ui.selectable_value(&mut target, Some(other), name(Some(other)));
+                }
+            });
+        match (target, &mut wave.modulates) {
+            (None, modulates) => *modulates = None,
+            (Some(target), Some(modulation)) => modulation.target = target,
+            (Some(target), modulates) => {
+                *modulates = Some(Modulation {
+                    target,
+                    index: Curve::constant(1.0),
+                })
+            }
+        }
+        ui.add(
+            DragValue::new(&mut wave.feedback)
+                .range(0.0..=10.0)
+                .speed(0.01)
+                .prefix("Feedback "),
+        );
+    });
+    if let Some(modulation) = &mut wave.modulates {
+        ui_curve(ui, &mut modulation.index, "Modulation index");
+    }
+}
+
+fn ui_combine(ui: &mut Ui, combine: &mut Combine, i: usize) {
+    ui.horizontal(|ui| {
+        let name = match combine {
+            Combine::Add => "Add",
+            Combine::Ring { .. } => "Ring modulate",
+            Combine::Am { .. } => "Amplitude modulate",
+        };
+        let source = combine.source_mut().and_then(|source| *source);
+        ComboBox::from_id_salt(ui.make_persistent_id("combine"))
+            .selected_text(name)
+            .show_ui(ui, |ui| {
+                if ui.selectable_label(name == "Add", "Add").clicked() {
+                    *combine = Combine::Add;
+                }
+                if ui
+                    .selectable_label(name == "Ring modulate", "Ring modulate")
+                    .clicked()
+                {
+                    *combine = Combine::Ring { source };
+                }
+                if ui
+                    .selectable_label(name == "Amplitude modulate", "Amplitude modulate")
+                    .clicked()
+                {
+                    *combine = Combine::Am { source, depth: 1.0 };
+                }
+            });
+        if let Some(source) = combine.source_mut() {
+            let source_name = |source: Option<usize>| match source {
+                Some(source) => format!("wave {}", source + 1),
+                None => "preceding wave".to_owned(),
+            };
+            ComboBox::from_id_salt(ui.make_persistent_id("combine source"))
+                .selected_text(source_name(*source))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(source, None, source_name(None));
+                    for other in 0..i {
+                        ui.selectable_value(source, Some(other), source_name(Some(other)));
+                    }
+                });
         }
-        if ui.button("-").clicked() && curve.0.len() > 1 {
-            curve.0.pop();
+        if let Combine::Am { depth, .. } = combine {
+            ui.add(
+                DragValue::new(depth)
+                    .range(0.0..=1.0)
+                    .speed(0.01)
+                    .prefix("Depth "),
+            );
         }
-        for v in curve.0.iter_mut() {
-            ui.add(DragValue::new(v).range(0.0..=f32::INFINITY).speed(0.01));
+    });
+}
+
+fn ui_filter(ui: &mut Ui, filter: &mut Option<Filter>, label: &str) {
+    ui.horizontal(|ui| {
+        let mut enabled = filter.is_some();
+        if ui.checkbox(&mut enabled, label).changed() {
+            *filter = enabled.then(Filter::default);
+        }
+        if let Some(filter) = filter {
+            ComboBox::from_id_salt(ui.make_persistent_id(label).with("mode"))
+                .width(80.0)
+                .selected_text(filter_mode_name(filter.mode))
+                .show_ui(ui, |ui| {
+                    for mode in [
+                        FilterMode::LowPass,
+                        FilterMode::HighPass,
+                        FilterMode::BandPass,
+                        FilterMode::Notch,
+                    ] {
+                        ui.selectable_value(&mut filter.mode, mode, filter_mode_name(mode));
+                    }
+                });
+            ui.add(
+                DragValue::new(&mut filter.key_tracking)
+                    .range(0.0..=2.0)
+                    .speed(0.01)
+                    .prefix("Key tracking "),
+            );
         }
     });
+    if let Some(filter) = filter {
+        ui_control(ui, &mut filter.cutoff, "Cutoff");
+        ui_control(ui, &mut filter.resonance, "Resonance");
+    }
 }
 
+fn filter_mode_name(mode: FilterMode) -> &'static str {
+    match mode {
+        FilterMode::LowPass => "Low-pass",
+        FilterMode::HighPass => "High-pass",
+        FilterMode::BandPass => "Band-pass",
+        FilterMode::Notch => "Notch",
+    }
+}
+
+fn ui_envelope(ui: &mut Ui, envelope: &mut Envelope) {
+    ui.horizontal(|ui| {
+        for (value, name) in [
+            (&mut envelope.delay, "Delay "),
+            (&mut envelope.attack, "Attack "),
+            (&mut envelope.hold, "Hold "),
+            (&mut envelope.decay, "Decay "),
+        ] {
+            ui.add(
+                DragValue::new(value)
+                    .range(0.0..=60.0)
+                    .speed(0.005)
+                    .prefix(name)
+                    .suffix(" s"),
+            );
+        }
+        ui.add(
+            DragValue::new(&mut envelope.sustain)
+                .range(0.0..=1.0)
+                .speed(0.005)
+                .prefix("Sustain "),
+        );
+        ui.add(
+            DragValue::new(&mut envelope.release)
+                .range(0.0..=60.0)
+                .speed(0.005)
+                .prefix("Release ")
+                .suffix(" s"),
+        );
+    });
+    ui.horizontal(|ui| {
+        ui.label("Curvature");
+        for (value, name) in [
+            (&mut envelope.attack_curvature, "Attack "),
+            (&mut envelope.decay_curvature, "Decay "),
+            (&mut envelope.release_curvature, "Release "),
+        ] {
+            ui.add(
+                DragValue::new(value)
+                    .range(-20.0..=20.0)
+                    .speed(0.1)
+                    .prefix(name),
+            );
+        }
+    });
+}
+
+fn ui_curve(ui: &mut Ui, curve: &mut Curve, label: &str) {
+    let id = ui.make_persistent_id(label);
+    ui.horizontal(|ui| {
+        ui.label(label);
+        ui_curve_header(ui, id, curve);
+    });
+    curve_editor(ui, id, curve);
+}
+
+/// Duration and end behavior of a curve, shown next to its label
+fn ui_curve_header(ui: &mut Ui, id: Id, curve: &mut Curve) {
+    let mut duration = curve.duration();
+    if ui
+        .add(
+            DragValue::new(&mut duration)
+                .range(0.0..=f32::INFINITY)
+                .speed(0.01)
+                .suffix(" s"),
+        )
+        .changed()
+    {
+        curve.set_duration(duration);
+    }
+    ComboBox::from_id_salt(id.with("end"))
+        .width(80.0)
+        .selected_text(curve_end_name(curve.end))
+        .show_ui(ui, |ui| {
+            for end in [CurveEnd::Hold, CurveEnd::Loop, CurveEnd::PingPong] {
+                ui.selectable_value(&mut curve.end, end, curve_end_name(end));
+            }
+        });
+}
+
+fn curve_end_name(end: CurveEnd) -> &'static str {
+    match end {
+        CurveEnd::Hold => "Hold",
+        CurveEnd::Loop => "Loop",
+        CurveEnd::PingPong => "Ping-pong",
+    }
+}
+
+/// Shows the wave at index `i`, returning whether to keep it
 fn wave_ui(
     wave: &mut Wave,
-    i: &mut usize,
+    i: usize,
     len: usize,
     swap: &mut Vec<(usize, usize)>,
+    toasts: &mut Toasts,
     ui: &mut Ui,
 ) -> bool {
     ui.add_space(25.0);
 
     ui.horizontal(|ui| {
         ui.vertical(|ui| {
-            if ui.button("^").clicked() && *i != 0 {
-                swap.push((*i, *i - 1));
+            ui.label(format!("{}", i + 1));
+            if ui.button("^").clicked() && i != 0 {
+                swap.push((i, i - 1));
             }
-            if ui.button("v").clicked() && *i != len - 1 {
-                swap.push((*i, *i + 1));
+            if ui.button("v").clicked() && i != len - 1 {
+                swap.push((i, i + 1));
             }
         });
         ui.add_space(10.0);
         ui.vertical(|ui| {
-            ui_curve(ui, &mut wave.amp, "Volume");
+            ui_control(ui, &mut wave.amp, "Volume");
             ui_curve(ui, &mut wave.freq, "Relative frequency");
 
             let response = ui.button("Waveform");
@@ -224,87 +788,161 @@ fn wave_ui(
                     ui.selectable_value(&mut wave.waveform, Waveform::Sawtooth, "Sawtooth");
                     ui.selectable_value(&mut wave.waveform, Waveform::Square, "Square");
                     ui.selectable_value(&mut wave.waveform, Waveform::WhiteNoise, "White noise");
+                    let is_table = matches!(wave.waveform, Waveform::Table(_));
+                    if ui.selectable_label(is_table, "Table").clicked() && !is_table {
+                        // Start from the current shape
+                        let (waveform, width) = (&wave.waveform, wave.width.at(0.0));
+                        let table = Wavetable::from_fn(|t| waveform.at(t as f64, width, 0.0));
+                        wave.waveform = Waveform::Table(table);
+                    }
                 });
-
-            let retain = !ui.button("Remove wave").clicked();
-            if retain {
-                *i += 1;
+            if ui.button("Import WAV").clicked()
+                && let Some(path) = file_dialog("WAV", "wav").pick_file()
+            {
+                match import_wavetable(&path) {
+                    Ok(table) => wave.waveform = Waveform::Table(table),
+                    Err(err) => toasts.error(format!("Could not import {}: {err}", path.display())),
+                }
             }
-            retain
+            ui.checkbox(&mut wave.bandlimited, "Band-limited");
+            match &mut wave.waveform {
+                Waveform::Square => ui_curve(ui, &mut wave.width, "Pulse width"),
+                Waveform::Triangle => ui_curve(ui, &mut wave.width, "Symmetry"),
+                Waveform::Table(table) => {
+                    let id = ui.make_persistent_id("wavetable");
+                    wavetable_editor(ui, id, table);
+                    if table.frames() > 1 {
+                        ui_curve(ui, &mut wave.position, "Position");
+                    }
+                }
+                _ => {}
+            }
+            ui_filter(ui, &mut wave.filter, "Filter");
+            ui_modulation(ui, wave, i, len);
+            ui_combine(ui, &mut wave.combine, i);
+
+            !ui.button("Remove wave").clicked()
         })
     })
     .inner
     .inner
 }
 
-fn setup_audio(app: MyApp) {
-    let host = cpal::default_host();
-    let device = host
-        .default_output_device()
-        .expect("No output device available");
-    let default_config = device
-        .default_output_config()
-        .expect("Could not get default output config");
-    println!("{default_config:?}");
-    let config = default_config.config();
-    match default_config.sample_format() {
-        cpal::SampleFormat::I8 => setup_stream::<i8>(device, config, app),
-        cpal::SampleFormat::I16 => setup_stream::<i16>(device, config, app),
-        cpal::SampleFormat::I24 => setup_stream::<I24>(device, config, app),
-        cpal::SampleFormat::I32 => setup_stream::<i32>(device, config, app),
-        cpal::SampleFormat::I64 => setup_stream::<i64>(device, config, app),
-        cpal::SampleFormat::U8 => setup_stream::<u8>(device, config, app),
-        cpal::SampleFormat::U16 => setup_stream::<u16>(device, config, app),
-        cpal::SampleFormat::U32 => setup_stream::<u32>(device, config, app),
-        cpal::SampleFormat::U64 => setup_stream::<u64>(device, config, app),
-        cpal::SampleFormat::F32 => setup_stream::<f32>(device, config, app),
-        cpal::SampleFormat::F64 => setup_stream::<f64>(device, config, app),
-        sample_format => panic!("Unsupported sample format '{sample_format}'"),
+/// Opens the output chosen in [`Audio::settings`], recording the outcome in
+/// [`Audio::status`]
+fn setup_audio(audio: Arc<Mutex<Audio>>) {
+    if let Err(err) = open_output(audio.clone()) {
+        let mut lock = audio.lock();
+        lock.stream = None;
+        lock.commands = None;
+        lock.status = Status::NoDevice(err);
+    }
+}
+
+/// Opens the output chosen in [`Audio::settings`], using the defaults of the
+/// host and device for anything not chosen
+fn open_output(audio: Arc<Mutex<Audio>>) -> Result<(), String> {
+    let settings = audio.lock().settings.clone();
+    let host = output::host(&settings);
+    let device = output::device(&host, &settings).ok_or("No output device available")?;
+    let supported = output::config(&device, &settings)?;
+    let mut config = supported.config();
+    if let Some(frames) = settings.buffer_size {
+        config.buffer_size = BufferSize::Fixed(frames);
+    }
+    match supported.sample_format() {
+        cpal::SampleFormat::I8 => setup_stream::<i8>(device, config, audio),
+        cpal::SampleFormat::I16 => setup_stream::<i16>(device, config, audio),
+        cpal::SampleFormat::I24 => setup_stream::<I24>(device, config, audio),
+        cpal::SampleFormat::I32 => setup_stream::<i32>(device, config, audio),
+        cpal::SampleFormat::I64 => setup_stream::<i64>(device, config, audio),
+        cpal::SampleFormat::U8 => setup_stream::<u8>(device, config, audio),
+        cpal::SampleFormat::U16 => setup_stream::<u16>(device, config, audio),
+        cpal::SampleFormat::U32 => setup_stream::<u32>(device, config, audio),
+        cpal::SampleFormat::U64 => setup_stream::<u64>(device, config, audio),
+        cpal::SampleFormat::F32 => setup_stream::<f32>(device, config, audio),
+        cpal::SampleFormat::F64 => setup_stream::<f64>(device, config, audio),
+        sample_format => Err(format!("Unsupported sample format '{sample_format}'")),
     }
 }
 
 fn setup_stream<T: SizedSample + FromSample<f32> + 'static>(
     device: Device,
     config: StreamConfig,
-    app: MyApp,
-) {
-    let app1 = app.clone();
-    let app2 = app.clone();
+    audio: Arc<Mutex<Audio>>,
+) -> Result<(), String> {
+    // Hold the lock until the new sender is stored so no update gets lost
+    let mut lock = audio.lock();
+    // Close the old stream first, as some devices can only be opened once
+    lock.stream = None;
+    lock.commands = None;
+    let (commands, receiver) = mpsc::channel();
+    let (old_timbres, old_timbres_receiver) = mpsc::channel();
+    let mut playback = Playback {
+        commands: receiver,
+        old_timbres,
+        channels: config.channels as usize,
+        synth: Synth::new(lock.timbre.clone(), config.sample_rate.0),
+        tap: lock.tap.clone(),
+    };
+    playback.tap.set_sample_rate(config.sample_rate.0);
+    let controls = &lock.controls;
+    playback
+        .synth
+        .set_polyphony(controls.max_voices, controls.steal);
+    if controls.hold {
+        playback.synth.note_on(SLIDER_KEY, controls.hz, 1.0);
+    }
+    let description = format!(
+        "{} ({} Hz, {} channels)",
+        device.name().unwrap_or_default(),
+        config.sample_rate.0,
+        config.channels
+    );
+    let audio1 = audio.clone();
     let stream = device
         .build_output_stream(
             &config,
-            move |data, _| write_data::<T>(data, app1.clone()),
+            move |data, _| write_data::<T>(data, &mut playback),
             move |err| {
-                eprintln!("Error: {err}\nRetrying...");
-                let app = app2.clone();
-                thread::spawn(move || setup_audio(app));
+                let audio = audio1.clone();
+                // Rebuilding the stream from its own callback would deadlock
+                thread::spawn(move || {
+                    audio.lock().status = Status::Reconnecting(err.to_string());
+                    setup_audio(audio);
+                });
             },
             None,
         )
-        .expect("Could not build output stream");
-    stream.play().expect("Could not play stream");
-    let mut lock = app.0.lock();
-    lock.stream_config = Some(config.clone());
+        .map_err(|err| err.to_string())?;
+    stream.play().map_err(|err| err.to_string())?;
+    lock.commands = Some(commands);
+    lock.old_timbres = Some(old_timbres_receiver);
     lock.stream = Some(stream);
+    lock.status = Status::Playing(description);
+    Ok(())
 }
 
-fn write_data<T: SizedSample + FromSample<f32>>(data: &mut [T], app: MyApp) {
-    let mut playback = app.0.lock();
-    let Some(stream_config) = playback.stream_config.clone() else {
-        eprintln!("Error: No stream config");
-        return;
-    };
-    for frame in data.chunks_mut(stream_config.channels as usize) {
-        let sec = playback.sample as f32 / stream_config.sample_rate.0 as f32;
-        let value = playback
-            .timbre
-            .waves
-            .iter()
-            .map(|wave| wave.at(sec, playback.hz))
-            .sum::<f32>()
-            * playback.timbre.amp.at(sec);
+fn write_data<T: SizedSample + FromSample<f32>>(data: &mut [T], playback: &mut Playback) {
+    while let Ok(command) = playback.commands.try_recv() {
+        match command {
+            Command::NoteOn { key, hz, velocity } => playback.synth.note_on(key, hz, velocity),
+            Command::NoteOff { key } => playback.synth.note_off(key),
+            Command::SetNoteHz { key, hz } => playback.synth.set_note_hz(key, hz),
+            Command::SetPolyphony { max_voices, steal } => {
+                playback.synth.set_polyphony(max_voices, steal)
+            }
+            Command::SetTimbre(mut timbre) => {
+                // Reuse the box to send back the previous timbre
+                *timbre = playback.synth.set_timbre(*timbre);
+                let _ = playback.old_timbres.send(timbre);
+            }
+        }
+    }
+    for frame in data.chunks_mut(playback.channels) {
+        let value = playback.synth.next_sample();
+        playback.tap.push(value);
         let value = T::from_sample(value);
-        playback.sample = (playback.sample + 1) % stream_config.sample_rate.0;
         for sample in frame {
             *sample = value;
         }
@@ -0,0 +1,352 @@
+//! MIDI keyboard input, through the ALSA sequencer on Linux.
+//!
+//! Connecting always creates a virtual input port other programs can send to
+//! (e.g. with `aconnect` or `aplaymidi`), optionally subscribed to an existing port.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread::JoinHandle,
+};
+
+use eframe::egui::{ComboBox, DragValue, Ui, mutex::Mutex};
+
+use crate::{Audio, Command};
+
+/// A MIDI port that can be connected to
+#[derive(Clone)]
+pub struct Port {
+    name: String,
+    addr: backend::Addr,
+}
+
+#[derive(Clone, Copy)]
+enum Event {
+    NoteOn { note: u8, velocity: u8 },
+    NoteOff { note: u8 },
+}
+
+/// An open MIDI input, closed when dropped
+struct Connection {
+    /// Address of the virtual port, for display
+    name: String,
+    stop: Arc<AtomicBool>,
+    /// Returns the error that stopped the input, if any
+    thread: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// MIDI settings of the GUI
+#[derive(Default)]
+pub struct Midi {
+    ports: Vec<Port>,
+    /// Port to subscribe to, or `None` for only the virtual port
+    source: Option<usize>,
+    connection: Option<Connection>,
+    error: Option<String>,
+}
+
+impl Midi {
+    fn connect(&mut self, audio: &Arc<Mutex<Audio>>) {
+        self.connection = None;
+        let source = self.source.map(|i| self.ports[i].clone());
+        let stop = Arc::new(AtomicBool::new(false));
+        let audio = audio.clone();
+        let on_event = move |event| dispatch(&audio, event);
+        match backend::connect(source.map(|port| port.addr), stop.clone(), on_event) {
+            Ok((name, thread)) => {
+                self.connection = Some(Connection {
+                    name,
+                    stop,
+                    thread: Some(thread),
+                });
+                self.error = None;
+            }
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    /// Closes the connection with its error if its thread has stopped
+    fn check_connection(&mut self) {
+        let Some(connection) = &mut self.connection else {
+            return;
+        };
+        if connection
+            .thread
+            .as_ref()
+            .is_some_and(|thread| thread.is_finished())
+        {
+            let result = connection.thread.take().map(|thread| thread.join());
+            self.connection = None;
+            self.error = Some(match result {
+                Some(Ok(Err(err))) => err,
+                _ => "MIDI input stopped unexpectedly".to_owned(),
+            });
+        }
+    }
+}
+
+/// Frequency of MIDI `note` in equal temperament with A4 (note 69) at `a4` hz
+pub fn note_hz(note: u8, a4: f32) -> f32 {
+    a4 * 2f32.powf((note as f32 - 69.0) / 12.0)
+}
+
+fn dispatch(audio: &Mutex<Audio>, event: Event) {
+    let audio = audio.lock();
+    audio.send(command(event, audio.controls.a4));
+}
+
+/// Command playing `event`, tuned to `a4` hz
+fn command(event: Event, a4: f32) -> Command {
+    match event {
+        // Note-on with velocity 0 is a common way to send note-off
+        Event::NoteOn { note, velocity: 0 } | Event::NoteOff { note } => {
+            Command::NoteOff { key: note as u32 }
+        }
+        Event::NoteOn { note, velocity } => Command::NoteOn {
+            key: note as u32,
+            hz: note_hz(note, a4),
+            velocity: velocity as f32 / 127.0,
+        },
+    }
+}
+
+pub fn ui_midi(ui: &mut Ui, midi: &mut Midi, a4: &mut f32, audio: &Arc<Mutex<Audio>>) {
+    midi.check_connection();
+    ui.horizontal(|ui| {
+        ui.label("MIDI");
+        let selected = match midi.source {
+            Some(i) => midi.ports[i].name.as_str(),
+            None => "Virtual port only",
+        };
+        ComboBox::from_id_salt("midi source")
+            .selected_text(selected)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut midi.source, None, "Virtual port only");
+                for (i, port) in midi.ports.iter().enumerate() {
+                    ui.selectable_value(&mut midi.source, Some(i), &port.name);
+                }
+            });
+        if ui.button("Refresh").clicked() {
+            midi.source = None;
+            match backend::ports() {
+                Ok(ports) => midi.ports = ports,
+                Err(err) => midi.error = Some(err),
+            }
+        }
+        if ui.button("Connect").clicked() {
+            midi.connect(audio);
+        }
+        if midi.connection.is_some() && ui.button("Disconnect").clicked() {
+            midi.connection = None;
+        }
+        ui.add(
+            DragValue::new(a4)
+                .range(400.0..=480.0)
+                .speed(0.1)
+                .prefix("A4 = ")
+                .suffix(" Hz"),
+        );
+        if let Some(err) = &midi.error {
+            ui.colored_label(ui.visuals().error_fg_color, err);
+        } else if let Some(connection) = &midi.connection {
+            ui.label(format!("Listening on {}", connection.name));
+        }
+    });
+}
+
+#[cfg(target_os = "linux")]
+mod backend {
+    use std::{
+        io,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, Ordering},
+        },
+        thread::{self, JoinHandle},
+    };
+
+    use alsa::{
+        Direction,
+        poll::{Descriptors, poll},
+        seq::{
+            ClientIter, EvNote, EventType, PortCap, PortInfo, PortIter, PortSubscribe, PortType,
+            Seq,
+        },
+    };
+
+    use super::{Event, Port};
+
+    pub type Addr = alsa::seq::Addr;
+
+    /// Lists the ports that can be subscribed to
+    pub fn ports() -> Result<Vec<Port>, String> {
+        let seq = Seq::open(None, None, false).map_err(|err| err.to_string())?;
+        let own = seq.client_id().map_err(|err| err.to_string())?;
+        let mut ports = vec![];
+        for client in ClientIter::new(&seq) {
+            // Client 0 only has the system timer and announcements
+            if client.get_client() == 0 || client.get_client() == own {
+                continue;
+            }
+            for port in PortIter::new(&seq, client.get_client()) {
+                let caps = port.get_capability();
+                if caps.contains(PortCap::READ | PortCap::SUBS_READ)
+                    && !caps.contains(PortCap::NO_EXPORT)
+                {
+                    ports.push(Port {
+                        name: format!(
+                            "{}: {}",
+                            client.get_name().unwrap_or_default(),
+                            port.get_name().unwrap_or_default()
+                        ),
+                        addr: port.addr(),
+                    });
+                }
+            }
+        }
+        Ok(ports)
+    }
+
+    /// Opens a virtual input port, subscribed to `source` if given, and calls
+    /// `on_event` from a new thread until `stop` is set or polling fails,
+    /// which the thread returns.
+    /// Returns the address of the virtual port.
+    pub fn connect(
+        source: Option<Addr>,
+        stop: Arc<AtomicBool>,
+        mut on_event: impl FnMut(Event) + Send + 'static,
+    ) -> Result<(String, JoinHandle<Result<(), String>>), String> {
+        let err = |err: alsa::Error| err.to_string();
+        let seq = Seq::open(None, Some(Direction::Capture), true).map_err(err)?;
+        seq.set_client_name(c"Timbre Tweak").map_err(err)?;
+        let mut info = PortInfo::empty().map_err(err)?;
+        info.set_capability(PortCap::WRITE | PortCap::SUBS_WRITE);
+        info.set_type(PortType::MIDI_GENERIC | PortType::APPLICATION);
+        info.set_name(c"Input");
+        seq.create_port(&info).map_err(err)?;
+        let dest = Addr {
+            client: seq.client_id().map_err(err)?,
+            port: info.get_port(),
+        };
+        if let Some(source) = source {
+            let subscription = PortSubscribe::empty().map_err(err)?;
+            subscription.set_sender(source);
+            subscription.set_dest(dest);
+            seq.subscribe_port(&subscription).map_err(err)?;
+        }
+
+        let thread = thread::spawn(move || {
+            let poll_err = |err: alsa::Error| format!("Could not poll MIDI input: {err}");
+            let mut fds = (&seq, Some(Direction::Capture)).get().map_err(poll_err)?;
+            let mut input = seq.input();
+            while !stop.load(Ordering::Relaxed) {
+                // Wake up regularly to check `stop`
+                match poll(&mut fds, 100) {
+                    Ok(_) => {}
+                    // Interrupted by a signal, ALSA errors are negative
+                    Err(err)
+                        if io::Error::from_raw_os_error(-err.errno()).kind()
+                            == io::ErrorKind::Interrupted =>
+                    {
+                        continue;
+                    }
+                    Err(err) => return Err(poll_err(err)),
+                }
+                while let Ok(event) = input.event_input() {
+                    let note = event.get_data::<EvNote>();
+                    match (event.get_type(), note) {
+                        (EventType::Noteon, Some(note)) => on_event(Event::NoteOn {
+                            note: note.note,
+                            velocity: note.velocity,
+                        }),
+                        (EventType::Noteoff, Some(note)) => {
+                            on_event(Event::NoteOff { note: note.note })
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Ok(())
+        });
+        Ok((format!("{}:{}", dest.client, dest.port), thread))
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod backend {
+    use std::{
+        sync::{Arc, atomic::AtomicBool},
+        thread::JoinHandle,
+    };
+
+    use super::{Event, Port};
+
+    pub type Addr = ();
+
+    const UNSUPPORTED: &str = "MIDI input is only supported on Linux";
+
+    pub fn ports() -> Result<Vec<Port>, String> {
+        Err(UNSUPPORTED.to_owned())
+    }
+
+    pub fn connect(
+        _source: Option<Addr>,
+        _stop: Arc<AtomicBool>,
+        _on_event: impl FnMut(Event) + Send + 'static,
+    ) -> Result<(String, JoinHandle<Result<(), String>>), String> {
+        Err(UNSUPPORTED.to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notes_are_tuned_to_a4() {
+        assert_eq!(note_hz(69, 440.0), 440.0);
+        assert_eq!(note_hz(81, 440.0), 880.0);
+        assert_eq!(note_hz(57, 432.0), 216.0);
+        assert!((note_hz(60, 440.0) - 261.6256).abs() < 1e-3);
+    }
+
+    #[test]
+    fn events_map_to_commands() {
+        let on = command(
+            Event::NoteOn {
+                note: 69,
+                velocity: 127,
+            },
+            440.0,
+        );
+        assert!(matches!(
+            on,
+            Command::NoteOn {
+                key: 69,
+                hz: 440.0,
+                velocity: 1.0
+            }
+        ));
+        let off = command(Event::NoteOff { note: 60 }, 440.0);
+        assert!(matches!(off, Command::NoteOff { key: 60 }));
+        let silent = command(
+            Event::NoteOn {
+                note: 60,
+                velocity: 0,
+            },
+            440.0,
+        );
+        assert!(matches!(silent, Command::NoteOff { key: 60 }));
+    }
+}
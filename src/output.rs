@@ -0,0 +1,290 @@
+//! Choice of the audio host, output device and stream configuration,
+//! remembered between sessions
+
+use std::{env, fmt::Display, fs, path::PathBuf, sync::Arc};
+
+use cpal::{
+    Device, Host, SampleRate, SupportedBufferSize, SupportedStreamConfig,
+    SupportedStreamConfigRange,
+    traits::{DeviceTrait, HostTrait},
+};
+use eframe::egui::{ComboBox, Ui, mutex::Mutex};
+use serde::{Deserialize, Serialize};
+
+use crate::{Audio, Status, setup_audio};
+
+const SAMPLE_RATES: [u32; 7] = [22050, 44100, 48000, 88200, 96000, 176400, 192000];
+const BUFFER_SIZES: [u32; 7] = [64, 128, 256, 512, 1024, 2048, 4096];
+
+/// Output chosen by the user. `None` means the default of the host or device.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub host: Option<String>,
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    /// Frames per buffer
+    pub buffer_size: Option<u32>,
+    pub channels: Option<u16>,
+}
+
+fn settings_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .or_else(|| env::var_os("APPDATA"))
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(dir.join("timbre_tweak").join("output.json"))
+}
+
+impl Settings {
+    /// Reads the settings of the last session, or the defaults
+    pub fn load() -> Self {
+        settings_path()
+            .and_then(|path| fs::read(path).ok())
+            .and_then(|slice| serde_json::from_slice(&slice).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = settings_path().ok_or("No config directory")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|err| err.to_string())?;
+        fs::write(path, json).map_err(|err| err.to_string())
+    }
+}
+
+/// The chosen host if available, otherwise the default host
+pub fn host(settings: &Settings) -> Host {
+    cpal::available_hosts()
+        .into_iter()
+        .find(|id| settings.host.as_deref() == Some(id.name()))
+        .and_then(|id| cpal::host_from_id(id).ok())
+        .unwrap_or_else(cpal::default_host)
+}
+
+/// The chosen device if available, otherwise the default device
+pub fn device(host: &Host, settings: &Settings) -> Option<Device> {
+    settings
+        .device
+        .as_ref()
+        .and_then(|name| {
+            host.output_devices()
+                .ok()?
+                .find(|device| device.name().ok().as_ref() == Some(name))
+        })
+        .or_else(|| host.default_output_device())
+}
+
+/// The chosen channel count and sample rate, preferring the sample format
+/// of the default config
+pub fn config(device: &Device, settings: &Settings) -> Result<SupportedStreamConfig, String> {
+    let default = device
+        .default_output_config()
+        .map_err(|err| err.to_string())?;
+    let channels = settings.channels.unwrap_or(default.channels());
+    let sample_rate = SampleRate(settings.sample_rate.unwrap_or(default.sample_rate().0));
+    if (channels, sample_rate) == (default.channels(), default.sample_rate()) {
+        return Ok(default);
+    }
+    let mut configs: Vec<_> = device
+        .supported_output_configs()
+        .map_err(|err| err.to_string())?
+        .filter(|config| config.channels() == channels)
+        .collect();
+    configs.sort_by_key(|config| config.sample_format() != default.sample_format());
+    configs
+        .into_iter()
+        .find_map(|config| config.try_with_sample_rate(sample_rate))
+        .ok_or_else(|| {
+            format!(
+                "{channels} channels at {} Hz are not supported",
+                sample_rate.0
+            )
+        })
+}
+
+/// Settings panel state, with the options of the chosen host and device
+pub struct Output {
+    settings: Settings,
+    devices: Vec<String>,
+    configs: Vec<SupportedStreamConfigRange>,
+    /// Error saving the settings
+    error: Option<String>,
+}
+
+impl Output {
+    pub fn new(settings: Settings) -> Self {
+        let mut output = Self {
+            settings,
+            devices: vec![],
+            configs: vec![],
+            error: None,
+        };
+        output.refresh();
+        output
+    }
+
+    /// Lists the devices of the chosen host and the configs of the chosen device
+    fn refresh(&mut self) {
+        let host = host(&self.settings);
+        self.devices = host
+            .output_devices()
+            .map(|devices| devices.filter_map(|device| device.name().ok()).collect())
+            .unwrap_or_default();
+        self.configs = device(&host, &self.settings)
+            .and_then(|device| device.supported_output_configs().ok())
+            .map(|configs| configs.collect())
+            .unwrap_or_default();
+    }
+
+    fn sample_rates(&self) -> Vec<u32> {
+        SAMPLE_RATES
+            .into_iter()
+            .filter(|&rate| {
+                self.configs.iter().any(|config| {
+                    self.settings
+                        .channels
+                        .is_none_or(|n| n == config.channels())
+                        && (config.min_sample_rate().0..=config.max_sample_rate().0).contains(&rate)
+                })
+            })
+            .collect()
+    }
+
+    fn buffer_sizes(&self) -> Vec<u32> {
+        BUFFER_SIZES
+            .into_iter()
+            .filter(|size| {
+                self.configs
+                    .iter()
+                    .any(|config| match *config.buffer_size() {
+                        SupportedBufferSize::Range { min, max } => (min..=max).contains(size),
+                        SupportedBufferSize::Unknown => false,
+                    })
+            })
+            .collect()
+    }
+
+    fn channels(&self) -> Vec<u16> {
+        let mut channels: Vec<_> = self
+            .configs
+            .iter()
+            .map(|config| config.channels())
+            .collect();
+        channels.sort();
+        channels.dedup();
+        channels
+    }
+
+    /// Restarts the stream with the current settings and remembers them
+    fn apply(&mut self, audio: &Arc<Mutex<Audio>>) {
+        audio.lock().settings = self.settings.clone();
+        setup_audio(audio.clone());
+        self.error = self.settings.save().err();
+    }
+}
+
+/// Shows `value`, with `None` labeled as the default
+fn option_name<T: Display>(value: Option<&T>, suffix: &str) -> String {
+    match value {
+        Some(value) => format!("{value}{suffix}"),
+        None => "Default".to_owned(),
+    }
+}
+
+fn option_combo<T: Clone + PartialEq + Display>(
+    ui: &mut Ui,
+    label: &str,
+    value: &mut Option<T>,
+    options: &[T],
+    suffix: &str,
+) -> bool {
+    let old = value.clone();
+    ui.label(label);
+    ComboBox::from_id_salt(label)
+        .selected_text(option_name(value.as_ref(), suffix))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(value, None, "Default");
+            for option in options {
+                ui.selectable_value(
+                    value,
+                    Some(option.clone()),
+                    option_name(Some(option), suffix),
+                );
+            }
+        });
+    *value != old
+}
+
+pub fn ui_output(ui: &mut Ui, output: &mut Output, audio: &Arc<Mutex<Audio>>) {
+    ui.horizontal(|ui| {
+        let hosts: Vec<String> = cpal::available_hosts()
+            .into_iter()
+            .map(|id| id.name().to_owned())
+            .collect();
+        let mut changed = option_combo(ui, "Host", &mut output.settings.host, &hosts, "");
+        if changed {
+            output.settings.device = None;
+        }
+        let devices = output.devices.clone();
+        if option_combo(ui, "Device", &mut output.settings.device, &devices, "") {
+            changed = true;
+            output.settings.sample_rate = None;
+            output.settings.buffer_size = None;
+            output.settings.channels = None;
+        }
+        if changed {
+            output.refresh();
+        }
+        let channels = output.channels();
+        changed |= option_combo(ui, "Channels", &mut output.settings.channels, &channels, "");
+        let sample_rates = output.sample_rates();
+        changed |= option_combo(
+            ui,
+            "Sample rate",
+            &mut output.settings.sample_rate,
+            &sample_rates,
+            " Hz",
+        );
+        let buffer_sizes = output.buffer_sizes();
+        changed |= option_combo(
+            ui,
+            "Buffer",
+            &mut output.settings.buffer_size,
+            &buffer_sizes,
+            " frames",
+        );
+        if ui.button("Refresh").clicked() {
+            output.refresh();
+        }
+        if changed {
+            output.apply(audio);
+        }
+        if let Some(err) = &output.error {
+            ui.colored_label(ui.visuals().error_fg_color, err);
+        }
+    });
+    ui.horizontal(|ui| {
+        let status = audio.lock().status.clone();
+        match status {
+            Status::Playing(description) => {
+                ui.label(format!("Playing on {description}"));
+            }
+            Status::Reconnecting(err) => {
+                ui.colored_label(
+                    ui.visuals().warn_fg_color,
+                    format!("{err}, reconnecting..."),
+                );
+            }
+            Status::NoDevice(err) => {
+                ui.colored_label(ui.visuals().error_fg_color, format!("No audio: {err}"));
+                if ui.button("Retry").clicked() {
+                    output.refresh();
+                    setup_audio(audio.clone());
+                }
+            }
+        }
+    });
+}
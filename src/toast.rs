@@ -0,0 +1,66 @@
+//! Messages shown in a corner of the window without blocking the editor
+
+use std::time::{Duration, Instant};
+
+use eframe::egui::{Align2, Area, Context, Frame, Id, RichText, vec2};
+
+/// How long messages that are not errors stay visible
+const INFO_DURATION: Duration = Duration::from_secs(4);
+
+struct Toast {
+    text: String,
+    error: bool,
+    created: Instant,
+}
+
+/// Messages to show until they expire or, for errors, until closed
+#[derive(Default)]
+pub struct Toasts {
+    toasts: Vec<Toast>,
+}
+
+impl Toasts {
+    pub fn info(&mut self, text: impl Into<String>) {
+        self.push(text.into(), false);
+    }
+
+    pub fn error(&mut self, text: impl Into<String>) {
+        self.push(text.into(), true);
+    }
+
+    fn push(&mut self, text: String, error: bool) {
+        self.toasts.push(Toast {
+            text,
+            error,
+            created: Instant::now(),
+        });
+    }
+
+    pub fn show(&mut self, ctx: &Context) {
+        self.toasts
+            .retain(|toast| toast.error || toast.created.elapsed() < INFO_DURATION);
+        if self.toasts.is_empty() {
+            return;
+        }
+        Area::new(Id::new("toasts"))
+            .anchor(Align2::RIGHT_BOTTOM, vec2(-10.0, -10.0))
+            .show(ctx, |ui| {
+                ui.set_max_width(400.0);
+                self.toasts.retain(|toast| {
+                    Frame::popup(ui.style())
+                        .show(ui, |ui| {
+                            ui.horizontal(|ui| {
+                                let mut text = RichText::new(&toast.text);
+                                if toast.error {
+                                    text = text.color(ui.visuals().error_fg_color);
+                                }
+                                ui.label(text);
+                                !ui.small_button("x").clicked()
+                            })
+                            .inner
+                        })
+                        .inner
+                });
+            });
+    }
+}
@@ -0,0 +1,111 @@
+//! Plot for drawing the frames of a [`Wavetable`]
+
+use eframe::egui::{DragValue, Id, Pos2, Rect, Response, Sense, Stroke, Ui, pos2, vec2};
+use timbre_synth::{TABLE_SIZE, Wavetable};
+
+const HEIGHT: f32 = 100.0;
+
+/// Drawing state kept in egui memory between frames
+#[derive(Clone, Copy, Default)]
+struct State {
+    /// Frame shown and drawn on
+    frame: usize,
+    /// Table position of the pointer in the previous frame of a drag
+    last: Option<(f32, f32)>,
+}
+
+/// Maps between table coordinates and screen positions
+struct View {
+    rect: Rect,
+}
+
+impl View {
+    fn to_screen(&self, cycle: f32, value: f32) -> Pos2 {
+        pos2(
+            self.rect.left() + cycle * self.rect.width(),
+            self.rect.center().y - value * self.rect.height() / 2.0,
+        )
+    }
+
+    /// Sample index and value under `pos`
+    fn to_table(&self, pos: Pos2) -> (f32, f32) {
+        let cycle = ((pos.x - self.rect.left()) / self.rect.width()).clamp(0.0, 1.0);
+        let value = (self.rect.center().y - pos.y) / self.rect.height() * 2.0;
+        (cycle * (TABLE_SIZE - 1) as f32, value.clamp(-1.0, 1.0))
+    }
+}
+
+/// Shows a frame of `table`, which can be drawn over by dragging
+pub fn wavetable_editor(ui: &mut Ui, id: Id, table: &mut Wavetable) -> Response {
+    let mut state: State = ui.data(|data| data.get_temp(id)).unwrap_or_default();
+    state.frame = state.frame.min(table.frames() - 1);
+    if table.frames() > 1 {
+        let mut number = state.frame + 1;
+        ui.add(
+            DragValue::new(&mut number)
+                .range(1..=table.frames())
+                .prefix("Frame ")
+                .suffix(format!(" of {}", table.frames())),
+        );
+        state.frame = number - 1;
+    }
+
+    let width = ui.available_width().clamp(200.0, 600.0);
+    let (rect, response) = ui.allocate_exact_size(vec2(width, HEIGHT), Sense::drag());
+    let view = View { rect };
+
+    if response.dragged()
+        && let Some(pos) = response.interact_pointer_pos()
+    {
+        let (index, value) = view.to_table(pos);
+        // Fill the samples passed since the last frame, so fast strokes have
+        // no gaps
+        let (from_index, from_value) = state.last.unwrap_or((index, value));
+        table.edit(state.frame, |samples| {
+            let (start, end) = (from_index.min(index), from_index.max(index));
+            let stroke = start.round() as usize..=end.round() as usize;
+            for (sample, i) in samples[stroke.clone()].iter_mut().zip(stroke) {
+                let x = if end > start {
+                    (i as f32 - from_index) / (index - from_index)
+                } else {
+                    1.0
+                };
+                *sample = from_value + (value - from_value) * x.clamp(0.0, 1.0);
+            }
+        });
+        state.last = Some((index, value));
+    } else {
+        state.last = None;
+    }
+    ui.data_mut(|data| data.insert_temp(id, state));
+
+    paint(ui, &view, table.frame(state.frame));
+    response.on_hover_text("Drag to draw the cycle")
+}
+
+fn paint(ui: &Ui, view: &View, frame: &[f32]) {
+    let visuals = ui.visuals();
+    let painter = ui.painter_at(view.rect);
+    painter.rect_filled(view.rect, 2.0, visuals.extreme_bg_color);
+
+    let grid = Stroke::new(1.0, visuals.widgets.noninteractive.bg_stroke.color);
+    painter.hline(view.rect.x_range(), view.rect.center().y, grid);
+    for quarter in 1..4 {
+        painter.vline(
+            view.to_screen(quarter as f32 / 4.0, 0.0).x,
+            view.rect.y_range(),
+            grid,
+        );
+    }
+
+    // One point per pixel, from the samples under it
+    let width = view.rect.width() as usize;
+    let line = (0..=width)
+        .map(|x| {
+            let cycle = x as f32 / width as f32;
+            let sample = frame[(cycle * TABLE_SIZE as f32) as usize % TABLE_SIZE];
+            view.to_screen(cycle, sample)
+        })
+        .collect();
+    painter.line(line, Stroke::new(1.5, visuals.selection.stroke.color));
+}
@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{Curve, Shape, curve::CurveRepr};
+
+/// Where a note is in its lifetime
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoteTime {
+    /// Seconds into the note timeline, which loops while the note is sustained
+    pub timeline: f32,
+    /// Seconds since note-on
+    pub age: f32,
+    /// Seconds since note-off, if released
+    pub released: Option<f32>,
+}
+
+/// An envelope generator with delay, attack, hold, decay, sustain and release
+/// stages, following note-on and note-off.
+///
+/// Curvatures work as in [`Shape::Exponential`], with 0.0 for linear stages.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    /// Seconds before the attack starts
+    #[serde(default)]
+    pub delay: f32,
+    /// Seconds to rise from 0 to 1
+    pub attack: f32,
+    /// Seconds to stay at 1 before the decay
+    #[serde(default)]
+    pub hold: f32,
+    /// Seconds to fall from 1 to `sustain`
+    pub decay: f32,
+    /// Level kept until note-off
+    pub sustain: f32,
+    /// Seconds to fall to 0 after note-off
+    pub release: f32,
+    #[serde(default)]
+    pub attack_curvature: f32,
+    #[serde(default)]
+    pub decay_curvature: f32,
+    #[serde(default)]
+    pub release_curvature: f32,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self {
+            delay: 0.0,
+            attack: 0.01,
+            hold: 0.0,
+            decay: 0.2,
+            sustain: 0.7,
+            release: 0.3,
+            attack_curvature: 0.0,
+            decay_curvature: 0.0,
+            release_curvature: 0.0,
+        }
+    }
+}
+
+/// Value `x` of the way from `from` to `to`
+fn stage(from: f32, to: f32, x: f32, curvature: f32) -> f32 {
+    from + (to - from) * Shape::Exponential { curvature }.ease(x.clamp(0.0, 1.0))
+}
+
+impl Envelope {
+    /// Level `age` seconds after note-on while the note is held
+    fn held(&self, age: f32) -> f32 {
+        let mut t = age - self.delay;
+        if t < 0.0 {
+            return 0.0;
+        }
+        if t < self.attack {
+            return stage(0.0, 1.0, t / self.attack, self.attack_curvature);
+        }
+        t -= self.attack;
+        if t < self.hold {
+            return 1.0;
+        }
+        t -= self.hold;
+        if t < self.decay {
+            return stage(1.0, self.sustain, t / self.decay, self.decay_curvature);
+        }
+        self.sustain
+    }
+
+    /// Level at `time`, releasing from the level reached at note-off
+    pub fn at(&self, time: NoteTime) -> f32 {
+        match time.released {
+            None => self.held(time.age),
+            Some(released) if released >= self.release => 0.0,
+            Some(released) => {
+                let level = self.held(time.age - released);
+                stage(level, 0.0, released / self.release, self.release_curvature)
+            }
+        }
+    }
+
+    /// Whether the release has ended
+    pub fn is_finished(&self, time: NoteTime) -> bool {
+        time.released
+            .is_some_and(|released| released >= self.release)
+    }
+}
+
+/// A value over the lifetime of a note, from a curve along the note timeline
+/// or from an envelope
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, try_from = "ControlRepr")]
+pub enum Control {
+    Envelope(Envelope),
+    Curve(Curve),
+}
+
+/// Serialized forms of a [`Control`], which keep the error of an invalid curve
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ControlRepr {
+    Envelope(Envelope),
+    Curve(CurveRepr),
+}
+
+impl TryFrom<ControlRepr> for Control {
+    type Error = &'static str;
+
+    fn try_from(repr: ControlRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ControlRepr::Envelope(envelope) => Ok(Self::Envelope(envelope)),
+            ControlRepr::Curve(curve) => Curve::try_from(curve).map(Self::Curve),
+        }
+    }
+}
+
+impl From<Curve> for Control {
+    fn from(curve: Curve) -> Self {
+        Self::Curve(curve)
+    }
+}
+
+impl Control {
+    pub fn at(&self, time: NoteTime) -> f32 {
+        match self {
+            Self::Envelope(envelope) => envelope.at(time),
+            Self::Curve(curve) => curve.at(time.timeline),
+        }
+    }
+
+    /// Whether this is an envelope whose release has ended
+    pub fn is_finished(&self, time: NoteTime) -> bool {
+        match self {
+            Self::Envelope(envelope) => envelope.is_finished(time),
+            Self::Curve(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENVELOPE: Envelope = Envelope {
+        delay: 0.1,
+        attack: 0.2,
+        hold: 0.1,
+        decay: 0.2,
+        sustain: 0.5,
+        release: 0.4,
+        attack_curvature: 0.0,
+        decay_curvature: 0.0,
+        release_curvature: 0.0,
+    };
+
+    fn held(age: f32) -> NoteTime {
+        NoteTime {
+            timeline: age,
+            age,
+            released: None,
+        }
+    }
+
+    fn released(age: f32, released: f32) -> NoteTime {
+        NoteTime {
+            released: Some(released),
+            ..held(age)
+        }
+    }
+
+    fn assert_near(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-4, "{a} != {b}");
+    }
+
+    #[test]
+    fn envelope_stages() {
+        let levels = [
+            (0.05, 0.0),
+            (0.2, 0.5),
+            (0.35, 1.0),
+            (0.5, 0.75),
+            (10.0, 0.5),
+        ];
+        for (age, level) in levels {
+            assert_near(ENVELOPE.at(held(age)), level);
+        }
+        assert_near(ENVELOPE.at(released(10.2, 0.2)), 0.25);
+        assert_near(ENVELOPE.at(released(10.4, 0.4)), 0.0);
+        assert!(!ENVELOPE.is_finished(released(10.2, 0.2)));
+        assert!(ENVELOPE.is_finished(released(10.4, 0.4)));
+    }
+
+    #[test]
+    fn envelope_releases_from_the_level_reached() {
+        // Released halfway through the attack
+        assert_near(ENVELOPE.at(released(0.4, 0.2)), 0.25);
+        assert_near(ENVELOPE.at(released(0.1, 0.1)), 0.0);
+    }
+
+    #[test]
+    fn envelope_curvatures() {
+        // Positive curvatures start slowly, negative ones quickly
+        let slow = (2f32.exp() - 1.0) / (4f32.exp() - 1.0);
+        for (curvature, progress) in [(4.0, slow), (-4.0, 1.0 - slow)] {
+            let envelope = Envelope {
+                attack_curvature: curvature,
+                decay_curvature: curvature,
+                release_curvature: curvature,
+                ..ENVELOPE
+            };
+            assert_near(envelope.at(held(0.2)), progress);
+            assert_near(envelope.at(held(0.5)), 1.0 - 0.5 * progress);
+            assert_near(envelope.at(released(10.2, 0.2)), 0.5 - 0.5 * progress);
+            assert_near(envelope.at(held(10.0)), 0.5);
+        }
+    }
+}
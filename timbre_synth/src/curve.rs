@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+
+/// What a [`Curve`] does after its last point
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum CurveEnd {
+    /// Keep the last value
+    #[default]
+    Hold,
+    /// Start over from the first point
+    Loop,
+    /// Play backwards to the first point, then forwards again
+    PingPong,
+}
+
+/// How a [`Curve`] gets from one point to the next
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Shape {
+    #[default]
+    Linear,
+    /// Slow start for positive curvature, fast start for negative curvature
+    Exponential { curvature: f32 },
+    /// Keep the value until the next point
+    Step,
+    /// Ease in and out
+    Smooth,
+}
+
+impl Shape {
+    /// Maps progress `x` in range 0.0..=1.0 through the segment to progress in value
+    pub(crate) fn ease(self, x: f32) -> f32 {
+        match self {
+            Self::Linear => x,
+            Self::Exponential { curvature } if curvature.abs() < 1e-3 => x,
+            Self::Exponential { curvature } => (curvature * x).exp_m1() / curvature.exp_m1(),
+            Self::Step => 0.0,
+            Self::Smooth => x * x * (3.0 - 2.0 * x),
+        }
+    }
+}
+
+/// A breakpoint of a [`Curve`]
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Point {
+    /// Seconds into the note
+    pub time: f32,
+    pub value: f32,
+    /// Shape of the segment to the next point
+    #[serde(default)]
+    pub shape: Shape,
+}
+
+/// A curve through breakpoints sorted by time
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "CurveRepr")]
+pub struct Curve {
+    pub points: Vec<Point>,
+    pub end: CurveEnd,
+}
+
+/// Serialized forms of a [`Curve`], including older ones with evenly spaced values
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum CurveRepr {
+    /// Values spanning one second
+    Values(Vec<f32>),
+    Points {
+        points: Vec<Point>,
+        #[serde(default)]
+        end: CurveEnd,
+    },
+    /// Values spanning `duration` seconds
+    EvenValues {
+        points: Vec<f32>,
+        duration: f32,
+        #[serde(default)]
+        end: CurveEnd,
+    },
+}
+
+impl TryFrom<CurveRepr> for Curve {
+    type Error = &'static str;
+
+    fn try_from(repr: CurveRepr) -> Result<Self, Self::Error> {
+        let curve = match repr {
+            CurveRepr::Values(values) => Self::even(&values, 1.0),
+            CurveRepr::Points { points, end } => Self { points, end },
+            CurveRepr::EvenValues {
+                points,
+                duration,
+                end,
+            } => Self {
+                end,
+                ..Self::even(&points, duration)
+            },
+        };
+        if curve.points.is_empty() {
+            Err("a curve needs at least one point")
+        } else if !curve.points.is_sorted_by(|a, b| a.time <= b.time) {
+            Err("curve points must be sorted by time")
+        } else {
+            Ok(curve)
+        }
+    }
+}
+
+impl Curve {
+    pub fn constant(value: f32) -> Self {
+        Self::even(&[value], 0.0)
+    }
+
+    /// A linear curve through evenly spaced `values` over `duration` seconds
+    pub fn even(values: &[f32], duration: f32) -> Self {
+        let step = duration / (values.len().max(2) - 1) as f32;
+        let points = values
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| Point {
+                time: i as f32 * step,
+                value,
+                shape: Shape::Linear,
+            })
+            .collect();
+        Self {
+            points,
+            end: CurveEnd::Hold,
+        }
+    }
+
+    /// Time of the last point
+    pub fn duration(&self) -> f32 {
+        self.points.last().map_or(0.0, |point| point.time)
+    }
+
+    /// Stretches the curve in time so the last point is at `duration`
+    pub fn set_duration(&mut self, duration: f32) {
+        let old = self.duration();
+        for point in &mut self.points {
+            point.time = if old > 0.0 {
+                point.time * duration / old
+            } else {
+                duration
+            };
+        }
+    }
+
+    /// Value `sec` seconds into the note, or 0.0 for a curve without points
+    pub fn at(&self, sec: f32) -> f32 {
+        let duration = self.duration();
+        let t = match self.end {
+            CurveEnd::Loop if sec > duration && duration > 0.0 => sec % duration,
+            CurveEnd::PingPong if sec > duration && duration > 0.0 => {
+                duration - (sec % (2.0 * duration) - duration).abs()
+            }
+            _ => sec,
+        };
+        // Index of the first point after `t`
+        let i = self.points.partition_point(|point| point.time <= t);
+        if i == 0 {
+            self.points.first().map_or(0.0, |point| point.value)
+        } else if i == self.points.len() {
+            self.points[i - 1].value
+        } else {
+            let (a, b) = (self.points[i - 1], self.points[i]);
+            let x = (t - a.time) / (b.time - a.time);
+            a.value + (b.value - a.value) * a.shape.ease(x)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_curve_is_zero() {
+        let curve = Curve {
+            points: vec![],
+            end: CurveEnd::Loop,
+        };
+        assert_eq!(curve.at(0.0), 0.0);
+        assert_eq!(curve.at(1.0), 0.0);
+    }
+}
@@ -0,0 +1,38 @@
+use std::f32::consts::PI;
+
+/// In-place radix-2 FFT of the complex signal in `re` and `im`, without
+/// normalization. The length must be a power of two.
+pub fn fft(re: &mut [f32], im: &mut [f32]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        // Bit-reversal permutation
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let re_b = re[b] * cos - im[b] * sin;
+                let im_b = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - re_b;
+                im[b] = im[a] - im_b;
+                re[a] += re_b;
+                im[a] += im_b;
+            }
+        }
+        len <<= 1;
+    }
+}
@@ -0,0 +1,339 @@
+use std::{
+    error::Error as StdError,
+    fmt, fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize, de::Error};
+use serde_json::{Value, json};
+
+use crate::Timbre;
+
+/// Version of the file format written by [`TimbreFile::to_json`].
+///
+/// - 0: a bare [`Timbre`] object, as saved before files had a version
+/// - 1: `{"version", "metadata", "timbre"}`
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Upgrades a file from the version at its index plus one to the next version.
+/// Version 0 is first wrapped as version 1, as it is its timbre without metadata.
+const MIGRATIONS: [fn(Value) -> Value; FORMAT_VERSION as usize - 1] = [];
+
+/// Descriptive information saved with a timbre
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metadata {
+    pub name: String,
+    pub author: String,
+    pub tags: Vec<String>,
+    /// Seconds since the Unix epoch
+    pub created: Option<u64>,
+    /// Seconds since the Unix epoch
+    pub modified: Option<u64>,
+}
+
+impl Metadata {
+    /// Marks the timbre as modified now, and as created now if it is new
+    pub fn touch(&mut self) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        self.created.get_or_insert(now);
+        self.modified = Some(now);
+    }
+}
+
+/// Why [`TimbreFile::load`] failed
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    /// The file is not a valid timbre file. Includes the line and column if known.
+    Parse(serde_json::Error),
+    /// The file parses, but the timbre fails [`Timbre::validate`]
+    Invalid(String),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Parse(err) => write!(f, "invalid timbre file: {err}"),
+            Self::Invalid(err) => write!(f, "invalid timbre: {err}"),
+        }
+    }
+}
+
+impl StdError for LoadError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Parse(err) => Some(err),
+            Self::Invalid(_) => None,
+        }
+    }
+}
+
+/// Why [`TimbreFile::save`] failed
+#[derive(Debug)]
+pub enum SaveError {
+    Io(io::Error),
+    Serialize(serde_json::Error),
+    /// The timbre fails [`Timbre::validate`], so the file could not be loaded
+    Invalid(String),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Serialize(err) => write!(f, "could not serialize timbre: {err}"),
+            Self::Invalid(err) => write!(f, "invalid timbre: {err}"),
+        }
+    }
+}
+
+impl StdError for SaveError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Serialize(err) => Some(err),
+            Self::Invalid(_) => None,
+        }
+    }
+}
+
+/// A timbre with its metadata, as saved by the editor
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimbreFile {
+    pub metadata: Metadata,
+    pub timbre: Timbre,
+}
+
+#[derive(Deserialize)]
+struct Header {
+    #[serde(default)]
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct Current {
+    #[serde(default)]
+    metadata: Metadata,
+    timbre: Timbre,
+}
+
+#[derive(Serialize)]
+struct Versioned<'a> {
+    version: u32,
+    metadata: &'a Metadata,
+    timbre: &'a Timbre,
+}
+
+impl TimbreFile {
+    /// Parses a file of any version, upgrading older versions, and validates
+    /// the timbre
+    pub fn from_json(slice: &[u8]) -> Result<Self, LoadError> {
+        let file = Self::parse(slice).map_err(LoadError::Parse)?;
+        file.timbre.validate().map_err(LoadError::Invalid)?;
+        Ok(file)
+    }
+
+    fn parse(slice: &[u8]) -> serde_json::Result<Self> {
+        let Header { version } = serde_json::from_slice(slice)?;
+        if version > FORMAT_VERSION {
+            return Err(serde_json::Error::custom(format!(
+                "file version {version} is newer than the supported version {FORMAT_VERSION}"
+            )));
+        }
+        // Parse the versions that need no migration directly, so errors point
+        // into the file
+        let Current { metadata, timbre } = if version == FORMAT_VERSION {
+            serde_json::from_slice(slice)?
+        } else if version == 0 && FORMAT_VERSION == 1 {
+            Current {
+                metadata: Metadata::default(),
+                timbre: serde_json::from_slice(slice)?,
+            }
+        } else {
+            let value = serde_json::from_slice(slice)?;
+            serde_json::from_value(upgrade(version, value, &MIGRATIONS))?
+        };
+        Ok(Self { metadata, timbre })
+    }
+
+    /// Reads and parses a file of any version
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        let slice = fs::read(path).map_err(LoadError::Io)?;
+        Self::from_json(&slice)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SaveError> {
+        let json = self.to_json()?;
+        fs::write(path, json).map_err(SaveError::Io)
+    }
+
+    /// Serializes the file, refusing timbres that [`TimbreFile::from_json`]
+    /// would reject
+    pub fn to_json(&self) -> Result<String, SaveError> {
+        self.timbre.validate().map_err(SaveError::Invalid)?;
+        serde_json::to_string(&Versioned {
+            version: FORMAT_VERSION,
+            metadata: &self.metadata,
+            timbre: &self.timbre,
+        })
+        .map_err(SaveError::Serialize)
+    }
+}
+
+/// Upgrades `value`, a file of `version`, through `migrations` as in [`MIGRATIONS`]
+fn upgrade(version: u32, value: Value, migrations: &[fn(Value) -> Value]) -> Value {
+    let (version, value) = match version {
+        0 => (1, json!({"version": 1, "timbre": value})),
+        version => (version, value),
+    };
+    migrations[version as usize - 1..]
+        .iter()
+        .fold(value, |value, migrate| migrate(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Curve, CurveEnd, Modulation, Point, Shape, Waveform};
+
+    /// A timbre as saved before files had a version, with plain-array curves
+    const BARE: &str = r#"{"amp": [1.0, 0.0], "waves": [
+        {"waveform": "Sine", "freq": [1.0], "amp": [0.5, 0.25, 0.0]}
+    ]}"#;
+
+    #[test]
+    fn loads_bare_timbre() {
+        let file = TimbreFile::from_json(BARE.as_bytes()).unwrap();
+        assert_eq!(file.metadata, Metadata::default());
+        let timbre = file.timbre;
+        assert_eq!(timbre.waves.len(), 1);
+        assert_eq!(timbre.waves[0].waveform, Waveform::Sine);
+        assert_eq!(timbre.amp, Curve::even(&[1.0, 0.0], 1.0).into());
+        assert_eq!(
+            timbre.waves[0].amp,
+            Curve::even(&[0.5, 0.25, 0.0], 1.0).into()
+        );
+    }
+
+    #[test]
+    fn loads_even_values_with_duration() {
+        let json = r#"{"amp": {"points": [1.0, 0.0], "duration": 2.0, "end": "Loop"},
+            "waves": [{"waveform": "Square", "freq": [1.0], "amp": [1.0]}]}"#;
+        let timbre = TimbreFile::from_json(json.as_bytes()).unwrap().timbre;
+        let amp = Curve {
+            end: CurveEnd::Loop,
+            ..Curve::even(&[1.0, 0.0], 2.0)
+        };
+        assert_eq!(timbre.amp, amp.into());
+    }
+
+    #[test]
+    fn loads_breakpoints() {
+        let json = r#"{"amp": {"points": [
+                {"time": 0.0, "value": 0.0, "shape": {"Exponential": {"curvature": 2.0}}},
+                {"time": 0.5, "value": 1.0}
+            ], "end": "PingPong"},
+            "waves": [{"waveform": "Sawtooth", "freq": [1.0], "amp": [1.0]}]}"#;
+        let timbre = TimbreFile::from_json(json.as_bytes()).unwrap().timbre;
+        let amp = Curve {
+            points: vec![
+                Point {
+                    time: 0.0,
+                    value: 0.0,
+                    shape: Shape::Exponential { curvature: 2.0 },
+                },
+                Point {
+                    time: 0.5,
+                    value: 1.0,
+                    shape: Shape::Linear,
+                },
+            ],
+            end: CurveEnd::PingPong,
+        };
+        assert_eq!(timbre.amp, amp.into());
+    }
+
+    #[test]
+    fn loads_version_1() {
+        let json = format!(
+            r#"{{"version": 1, "metadata": {{"name": "Bell", "tags": ["metal"], "created": 5}},
+                "timbre": {BARE}}}"#
+        );
+        let file = TimbreFile::from_json(json.as_bytes()).unwrap();
+        assert_eq!(file.metadata.name, "Bell");
+        assert_eq!(file.metadata.tags, ["metal"]);
+        assert_eq!(file.metadata.created, Some(5));
+        assert_eq!(
+            file.timbre,
+            TimbreFile::from_json(BARE.as_bytes()).unwrap().timbre
+        );
+    }
+
+    #[test]
+    fn migrates_bare_timbres() {
+        // A version 2 that moves the timbre into a list of layers
+        fn layers(mut value: Value) -> Value {
+            let timbre = value["timbre"].take();
+            json!({"version": 2, "metadata": value["metadata"], "layers": [timbre]})
+        }
+        let value: Value = serde_json::from_str(BARE).unwrap();
+        let current: Current =
+            serde_json::from_value(upgrade(0, value.clone(), &MIGRATIONS)).unwrap();
+        assert_eq!(current.metadata, Metadata::default());
+        let upgraded = upgrade(0, value, &[layers]);
+        assert_eq!(upgraded["version"], 2);
+        let timbre: Timbre = serde_json::from_value(upgraded["layers"][0].clone()).unwrap();
+        assert_eq!(timbre, current.timbre);
+        // Files of the current version are left alone
+        let current = json!({"version": 2, "layers": []});
+        assert_eq!(upgrade(2, current.clone(), &[layers]), current);
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut file = TimbreFile::from_json(BARE.as_bytes()).unwrap();
+        file.metadata.name = "Bell".to_owned();
+        file.metadata.touch();
+        let json = file.to_json().unwrap();
+        assert!(json.contains(&format!(r#""version":{FORMAT_VERSION}"#)));
+        assert_eq!(TimbreFile::from_json(json.as_bytes()).unwrap(), file);
+    }
+
+    #[test]
+    fn bare_timbre_errors_have_locations() {
+        let json = r#"{"amp": [1.0], "waves": [{"waveform": "Saw", "freq": [1.0], "amp": [1.0]}]}"#;
+        let err = TimbreFile::from_json(json.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line 1 column"), "{err}");
+    }
+
+    #[test]
+    fn rejects_invalid_timbres() {
+        let json = r#"{"amp": [1.0], "waves": [{"waveform": "Sine", "freq": [1.0], "amp": [1.0],
+            "modulates": {"target": 0, "index": [1.0]}}]}"#;
+        let err = TimbreFile::from_json(json.as_bytes()).unwrap_err();
+        assert!(matches!(err, LoadError::Invalid(_)), "{err}");
+    }
+
+    #[test]
+    fn refuses_to_save_invalid_timbres() {
+        let mut file = TimbreFile::from_json(BARE.as_bytes()).unwrap();
+        file.timbre.waves[0].modulates = Some(Modulation {
+            target: 0,
+            index: Curve::constant(1.0),
+        });
+        assert!(matches!(file.to_json(), Err(SaveError::Invalid(_))));
+    }
+
+    #[test]
+    fn rejects_newer_versions() {
+        let json = format!(r#"{{"version": {}, "timbre": {BARE}}}"#, FORMAT_VERSION + 1);
+        let err = TimbreFile::from_json(json.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("newer"), "{err}");
+    }
+}
@@ -0,0 +1,163 @@
+use std::f32::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Control, Curve, NoteTime};
+
+/// Frequency range of [`Filter::cutoff`]
+const MIN_CUTOFF: f32 = 20.0;
+const MAX_CUTOFF: f32 = 20000.0;
+/// Note frequency at which key tracking leaves the cutoff unchanged
+const KEY_TRACKING_HZ: f32 = 440.0;
+
+/// Which band a [`Filter`] lets through
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum FilterMode {
+    #[default]
+    LowPass,
+    HighPass,
+    BandPass,
+    Notch,
+}
+
+/// A resonant state-variable filter
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Filter {
+    #[serde(default)]
+    pub mode: FilterMode,
+    /// Cutoff frequency from 0.0 for 20 Hz to 1.0 for 20 kHz, exponentially
+    pub cutoff: Control,
+    /// From 0.0 for none to 1.0 for nearly self-oscillating
+    pub resonance: Control,
+    /// How much the cutoff follows the note frequency, relative to A4 at
+    /// 440 Hz. 1.0 moves the cutoff by an octave per octave.
+    #[serde(default)]
+    pub key_tracking: f32,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            mode: FilterMode::LowPass,
+            cutoff: Curve::constant(0.7).into(),
+            resonance: Curve::constant(0.2).into(),
+            key_tracking: 0.0,
+        }
+    }
+}
+
+/// Integrator state of a [`Filter`] playing a note
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct FilterState {
+    ic1eq: f32,
+    ic2eq: f32,
+}
+
+impl Filter {
+    /// Cutoff in hz at `time` for a note at `hz`
+    pub fn cutoff_hz(&self, time: NoteTime, hz: f32) -> f32 {
+        let cutoff = MIN_CUTOFF * (MAX_CUTOFF / MIN_CUTOFF).powf(self.cutoff.at(time));
+        cutoff * (hz / KEY_TRACKING_HZ).powf(self.key_tracking)
+    }
+
+    /// Filters the next sample, using the trapezoidal integration of
+    /// Andrew Simper's SVF, which stays stable while the cutoff moves
+    pub(crate) fn next(
+        &self,
+        state: &mut FilterState,
+        input: f32,
+        time: NoteTime,
+        hz: f32,
+        sample_rate: f32,
+    ) -> f32 {
+        let cutoff = self
+            .cutoff_hz(time, hz)
+            .clamp(MIN_CUTOFF.min(sample_rate * 0.45), sample_rate * 0.45);
+        let g = (PI * cutoff / sample_rate).tan();
+        // Damping, from 2.0 without resonance
+        let k = 2.0 - 1.98 * self.resonance.at(time).clamp(0.0, 1.0);
+        let a1 = 1.0 / (1.0 + g * (g + k));
+        let a2 = g * a1;
+        let a3 = g * a2;
+
+        let v3 = input - state.ic2eq;
+        let band = a1 * state.ic1eq + a2 * v3;
+        let low = state.ic2eq + a2 * state.ic1eq + a3 * v3;
+        state.ic1eq = 2.0 * band - state.ic1eq;
+        state.ic2eq = 2.0 * low - state.ic2eq;
+
+        let high = input - k * band - low;
+        match self.mode {
+            FilterMode::LowPass => low,
+            FilterMode::HighPass => high,
+            FilterMode::BandPass => band,
+            FilterMode::Notch => low + high,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn filter(mode: FilterMode, cutoff: Curve, resonance: f32) -> Filter {
+        Filter {
+            mode,
+            cutoff: cutoff.into(),
+            resonance: Curve::constant(resonance).into(),
+            key_tracking: 0.0,
+        }
+    }
+
+    /// Peak output for a second of a sine at `hz`, after it settles
+    fn peak(filter: &Filter, hz: f32) -> f32 {
+        let mut state = FilterState::default();
+        (0..SAMPLE_RATE as usize)
+            .map(|i| {
+                let t = i as f32 / SAMPLE_RATE;
+                let time = NoteTime {
+                    timeline: t,
+                    age: t,
+                    released: None,
+                };
+                let input = (TAU * hz * t).sin();
+                (i, filter.next(&mut state, input, time, 440.0, SAMPLE_RATE))
+            })
+            .filter(|&(i, _)| i > SAMPLE_RATE as usize / 2)
+            .fold(0.0f32, |peak, (_, x)| peak.max(x.abs()))
+    }
+
+    #[test]
+    fn passes_its_band() {
+        // Cutoff at 632 Hz
+        let low_pass = filter(FilterMode::LowPass, Curve::constant(0.5), 0.0);
+        let high_pass = filter(FilterMode::HighPass, Curve::constant(0.5), 0.0);
+        assert!(peak(&low_pass, 100.0) > 0.95);
+        assert!(peak(&low_pass, 4000.0) < 0.05);
+        assert!(peak(&high_pass, 100.0) < 0.05);
+        assert!(peak(&high_pass, 4000.0) > 0.95);
+    }
+
+    #[test]
+    fn full_resonance_stays_bounded() {
+        let resonant = filter(FilterMode::LowPass, Curve::constant(0.5), 1.0);
+        let cutoff = 20.0 * 1000f32.sqrt();
+        let peak_at_cutoff = peak(&resonant, cutoff);
+        // Resonance of 1 / k = 50
+        assert!((40.0..=60.0).contains(&peak_at_cutoff), "{peak_at_cutoff}");
+        // Sweeping the cutoff down across the input after half a second
+        for mode in [
+            FilterMode::LowPass,
+            FilterMode::BandPass,
+            FilterMode::HighPass,
+        ] {
+            let sweep = filter(mode, Curve::even(&[1.0, 0.0], 1.0), 1.0);
+            let peak = peak(&sweep, 440.0);
+            assert!(peak.is_finite() && peak < 60.0, "{mode:?}: {peak}");
+        }
+    }
+}
@@ -0,0 +1,31 @@
+//! Synthesis engine of Timbre Tweak, usable without its GUI.
+//!
+//! ```no_run
+//! let json = std::fs::read("bell.json").unwrap();
+//! let timbre = timbre_synth::Timbre::from_json(&json).unwrap();
+//! let mut synth = timbre_synth::Synth::new(timbre, 48000);
+//! synth.note_on(69, 440.0, 1.0);
+//! let mut buffer = [0.0; 512];
+//! synth.fill(&mut buffer);
+//! ```
+
+mod control;
+mod curve;
+mod fft;
+mod file;
+mod filter;
+mod timbre;
+mod voice;
+pub mod wav;
+mod waveform;
+mod wavetable;
+
+pub use control::{Control, Envelope, NoteTime};
+pub use curve::{Curve, CurveEnd, Point, Shape};
+pub use fft::fft;
+pub use file::{FORMAT_VERSION, LoadError, Metadata, SaveError, TimbreFile};
+pub use filter::{Filter, FilterMode};
+pub use timbre::{Combine, MAX_LENGTH, Modulation, Sustain, Timbre, Wave};
+pub use voice::{StealPolicy, Synth, Voice, render};
+pub use waveform::Waveform;
+pub use wavetable::{TABLE_SIZE, Wavetable};
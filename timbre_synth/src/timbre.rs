@@ -0,0 +1,390 @@
+use std::f32::consts::TAU;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Control, Curve, Filter, LoadError, NoteTime, TimbreFile, Waveform, filter::FilterState,
+};
+
+/// A single oscillator of a [`Timbre`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Wave {
+    pub waveform: Waveform,
+    /// Whether to use the anti-aliased variant of the waveform
+    #[serde(default)]
+    pub bandlimited: bool,
+    pub freq: Curve,
+    /// Pulse width of [`Waveform::Square`] and symmetry of
+    /// [`Waveform::Triangle`], as the `width` of [`Waveform::at`]
+    #[serde(default = "default_width")]
+    pub width: Curve,
+    /// Position in the frames of [`Waveform::Table`], from 0.0 for the first
+    /// to 1.0 for the last
+    #[serde(default = "default_position")]
+    pub position: Curve,
+    pub amp: Control,
+    /// Applied before `amp`
+    #[serde(default)]
+    pub filter: Option<Filter>,
+    /// Makes this wave modulate the phase of another instead of being heard
+    #[serde(default)]
+    pub modulates: Option<Modulation>,
+    /// Phase modulation by the wave's own output, like `index` of [`Modulation`]
+    #[serde(default)]
+    pub feedback: f32,
+    #[serde(default)]
+    pub combine: Combine,
+}
+
+fn default_width() -> Curve {
+    Curve::constant(0.5)
+}
+
+fn default_position() -> Curve {
+    Curve::constant(0.0)
+}
+
+/// How a heard [`Wave`] joins the sound
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Combine {
+    /// Added to the other waves
+    #[default]
+    Add,
+    /// Multiplied with the source wave, which is then no longer heard by itself
+    Ring {
+        #[serde(default)]
+        source: Option<usize>,
+    },
+    /// Scales the source wave by a gain from `1.0 - depth` to 1.0,
+    /// replacing it like [`Combine::Ring`]
+    Am {
+        #[serde(default)]
+        source: Option<usize>,
+        depth: f32,
+    },
+}
+
+impl Combine {
+    /// Index of the wave combined with, which must come before this wave.
+    /// `None` stands for the preceding wave.
+    pub fn source_mut(&mut self) -> Option<&mut Option<usize>> {
+        match self {
+            Self::Add => None,
+            Self::Ring { source } | Self::Am { source, .. } => Some(source),
+        }
+    }
+
+    /// Index of the wave combined with by the wave at `index`, if valid
+    pub(crate) fn source(self, index: usize) -> Option<usize> {
+        match self {
+            Self::Add => None,
+            Self::Ring { source: None } | Self::Am { source: None, .. } => index.checked_sub(1),
+            Self::Ring {
+                source: Some(source),
+            }
+            | Self::Am {
+                source: Some(source),
+                ..
+            } => (source < index).then_some(source),
+        }
+    }
+}
+
+/// Phase modulation of one [`Wave`] by another
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Modulation {
+    /// Index of the modulated wave in [`Timbre::waves`]
+    pub target: usize,
+    /// Phase deviation in radians per unit of the modulator's output
+    pub index: Curve,
+}
+
+/// Synthesis state of a [`Wave`] playing a note
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct WaveState {
+    /// Phase in cycles
+    phase: f64,
+    filter: FilterState,
+    /// The last two outputs, for modulating other waves and feedback
+    pub(crate) output: f32,
+    previous: f32,
+}
+
+impl Wave {
+    /// Computes the next sample into `state.output`. `modulation` offsets the
+    /// phase in radians.
+    pub(crate) fn next(
+        &self,
+        state: &mut WaveState,
+        modulation: f32,
+        time: NoteTime,
+        hz: f32,
+        sample_rate: f32,
+    ) {
+        let dt = hz * self.freq.at(time.timeline) / sample_rate;
+        // Averaging two samples of feedback keeps it from oscillating at Nyquist
+        let feedback = self.feedback * (state.output + state.previous) / 2.0;
+        let phase = state.phase + ((modulation + feedback) / TAU) as f64;
+        let width = self.width.at(time.timeline);
+        let position = self.position.at(time.timeline);
+        let mut value = if self.bandlimited {
+            self.waveform.bandlimited_at(phase, dt, width, position)
+        } else {
+            self.waveform.at(phase, width, position)
+        };
+        state.phase += dt as f64;
+        if let Some(filter) = &self.filter {
+            value = filter.next(&mut state.filter, value, time, hz, sample_rate);
+        }
+        state.previous = state.output;
+        state.output = value * self.amp.at(time);
+    }
+}
+
+/// Part of the note timeline that loops while the note is held
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sustain {
+    /// Start of the loop in seconds
+    pub start: f32,
+    /// End of the loop in seconds, where the release starts.
+    /// Time stands still while held if this equals `start`.
+    pub end: f32,
+}
+
+/// Why [`Timbre::modulation_order`] failed, as wave indices so that
+/// [`Timbre::modulation_order_into`] does not allocate
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum OrderError {
+    MissingTarget { wave: usize, target: usize },
+    Cycle { wave: usize },
+}
+
+/// Longest [`Timbre::length`] in seconds, which keeps rendering bounded
+pub const MAX_LENGTH: f32 = 3600.0;
+
+/// A sound made of waves summed together, except for waves that modulate others
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Timbre {
+    /// An envelope here ends the note when its release ends, instead of `length`
+    pub amp: Control,
+    pub waves: Vec<Wave>,
+    /// Applied to the sum of the waves, before `amp`
+    #[serde(default)]
+    pub filter: Option<Filter>,
+    /// Without a sustain loop, notes play once to `length` even while held.
+    /// Defaults to looping the first second, as timbres did before notes had a lifecycle.
+    #[serde(default = "default_sustain")]
+    pub sustain: Option<Sustain>,
+    /// Length of the note timeline in seconds, after which a note ends unless
+    /// `amp` is an envelope
+    #[serde(default = "default_length")]
+    pub length: f32,
+}
+
+fn default_sustain() -> Option<Sustain> {
+    Some(Sustain {
+        start: 0.0,
+        end: 1.0,
+    })
+}
+
+fn default_length() -> f32 {
+    1.0
+}
+
+impl Timbre {
+    /// Order in which to compute the waves so modulators come before the waves
+    /// they modulate. Fails for invalid targets and modulation cycles.
+    pub fn modulation_order(&self) -> Result<Vec<usize>, String> {
+        let mut order = vec![];
+        match self.modulation_order_into(&mut order, &mut vec![]) {
+            Ok(()) => Ok(order),
+            Err(OrderError::MissingTarget { wave, target }) => Err(format!(
+                "wave {} modulates wave {}, which does not exist",
+                wave + 1,
+                target + 1
+            )),
+            Err(OrderError::Cycle { wave }) => {
+                Err(format!("wave {} is part of a modulation cycle", wave + 1))
+            }
+        }
+    }
+
+    /// Like [`Timbre::modulation_order`], but writes into `order` with
+    /// `pending` as scratch space, allocating only if they need to grow
+    pub(crate) fn modulation_order_into(
+        &self,
+        order: &mut Vec<usize>,
+        pending: &mut Vec<usize>,
+    ) -> Result<(), OrderError> {
+        let len = self.waves.len();
+        // Number of modulators of each wave not yet in the order
+        pending.clear();
+        pending.resize(len, 0);
+        for (i, wave) in self.waves.iter().enumerate() {
+            if let Some(modulation) = &wave.modulates {
+                if modulation.target >= len {
+                    return Err(OrderError::MissingTarget {
+                        wave: i,
+                        target: modulation.target,
+                    });
+                }
+                pending[modulation.target] += 1;
+            }
+        }
+        order.clear();
+        order.extend((0..len).filter(|&i| pending[i] == 0));
+        let mut next = 0;
+        while let Some(&i) = order.get(next) {
+            next += 1;
+            if let Some(modulation) = &self.waves[i].modulates {
+                pending[modulation.target] -= 1;
+                if pending[modulation.target] == 0 {
+                    order.push(modulation.target);
+                }
+            }
+        }
+        match (0..len).find(|&i| pending[i] > 0) {
+            Some(wave) => Err(OrderError::Cycle { wave }),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that the length and the release of a global envelope are in
+    /// range 0.0..=[`MAX_LENGTH`], that modulation targets and combine sources
+    /// exist, that there are no modulation cycles, and that combine sources
+    /// come before their waves
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=MAX_LENGTH).contains(&self.length) {
+            return Err(format!(
+                "the length of {} s is not between 0 s and {MAX_LENGTH} s",
+                self.length
+            ));
+        }
+        // The release decides when notes end, see `amp`
+        if let Control::Envelope(envelope) = &self.amp
+            && !(0.0..=MAX_LENGTH).contains(&envelope.release)
+        {
+            return Err(format!(
+                "the release of {} s is not between 0 s and {MAX_LENGTH} s",
+                envelope.release
+            ));
+        }
+        self.modulation_order()?;
+        for (i, wave) in self.waves.iter().enumerate() {
+            if let Combine::Ring {
+                source: Some(source),
+            }
+            | Combine::Am {
+                source: Some(source),
+                ..
+            } = wave.combine
+                && source >= i
+            {
+                return Err(format!(
+                    "wave {} combines with wave {}, which does not come before it",
+                    i + 1,
+                    source + 1
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the wave at `index`, along with modulations of it, keeping other
+    /// modulations and combine sources pointing at the same waves
+    pub fn remove_wave(&mut self, index: usize) -> Wave {
+        let wave = self.waves.remove(index);
+        for other in &mut self.waves {
+            if let Some(source) = other.combine.source_mut() {
+                *source = match *source {
+                    Some(source) if source == index => None,
+                    Some(source) if source > index => Some(source - 1),
+                    source => source,
+                };
+            }
+            if other
+                .modulates
+                .as_ref()
+                .is_some_and(|modulation| modulation.target == index)
+            {
+                other.modulates = None;
+            }
+            if let Some(modulation) = &mut other.modulates
+                && modulation.target > index
+            {
+                modulation.target -= 1;
+            }
+        }
+        wave
+    }
+
+    /// Swaps two waves, keeping modulations and combine sources pointing at
+    /// the same waves. Sources that end up after their wave change to the
+    /// preceding wave.
+    pub fn swap_waves(&mut self, a: usize, b: usize) {
+        self.waves.swap(a, b);
+        for (i, wave) in self.waves.iter_mut().enumerate() {
+            if let Some(source) = wave.combine.source_mut() {
+                *source = match *source {
+                    Some(source) if source == a => Some(b),
+                    Some(source) if source == b => Some(a),
+                    source => source,
+                }
+                .filter(|&source| source < i);
+            }
+            if let Some(modulation) = &mut wave.modulates {
+                if modulation.target == a {
+                    modulation.target = b;
+                } else if modulation.target == b {
+                    modulation.target = a;
+                }
+            }
+        }
+    }
+
+    /// Parses a timbre saved by the editor, ignoring its metadata.
+    /// See [`TimbreFile::from_json`].
+    pub fn from_json(slice: &[u8]) -> Result<Self, LoadError> {
+        TimbreFile::from_json(slice).map(|file| file.timbre)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unbounded_lengths() {
+        for length in ["-1.0", "1e30"] {
+            let json = format!(r#"{{"amp": [1.0], "waves": [], "length": {length}}}"#);
+            assert!(Timbre::from_json(json.as_bytes()).is_err(), "{length}");
+            let json = format!(
+                r#"{{"amp": {{"attack": 0.0, "decay": 0.0, "sustain": 1.0, "release": {length}}},
+                    "waves": []}}"#
+            );
+            assert!(
+                Timbre::from_json(json.as_bytes()).is_err(),
+                "release {length}"
+            );
+        }
+    }
+
+    #[test]
+    fn swapping_keeps_combine_sources_before_their_waves() {
+        let mut timbre = Timbre::from_json(
+            br#"{"amp": [1.0], "waves": [
+                {"waveform": "Sine", "freq": [1.0], "amp": [1.0]},
+                {"waveform": "Sine", "freq": [2.0], "amp": [1.0]},
+                {"waveform": "Sine", "freq": [3.0], "amp": [1.0],
+                 "combine": {"Ring": {"source": 0}}}
+            ]}"#,
+        )
+        .unwrap();
+        timbre.swap_waves(1, 2);
+        assert_eq!(timbre.waves[1].combine, Combine::Ring { source: Some(0) });
+        timbre.swap_waves(0, 1);
+        assert_eq!(timbre.waves[0].combine, Combine::Ring { source: None });
+        timbre.validate().unwrap();
+    }
+}
@@ -0,0 +1,493 @@
+use crate::{
+    Combine, Control, MAX_LENGTH, NoteTime, Timbre, filter::FilterState, timbre::WaveState,
+};
+
+/// Length of the fade-out at the end of a note, avoiding clicks
+const FADE_SECONDS: f32 = 0.01;
+
+/// Synthesis state of a single note played with a [`Timbre`]
+pub struct Voice {
+    sample_rate: u32,
+    /// Position in the note timeline
+    sample: u32,
+    /// Samples since note-on
+    age: u32,
+    /// Value of `age` at note-off, if released
+    released_at: Option<u32>,
+    /// Identifies the note for [`Synth::note_off`]
+    key: u32,
+    hz: f32,
+    velocity: f32,
+    /// Samples since the end of the timeline, if reached
+    fade: Option<u32>,
+    /// Order in which voices were started
+    started: u64,
+    /// Peak follower of the output, for stealing the quietest voice
+    level: f32,
+    /// State of each wave
+    waves: Vec<WaveState>,
+    /// Indices of the waves with modulators first, see [`Timbre::modulation_order`]
+    order: Vec<usize>,
+    /// Scratch space for computing `order`
+    pending: Vec<usize>,
+    /// Whether each wave is part of the sound, rather than a modulator or
+    /// the source of a combination
+    heard: Vec<bool>,
+    /// Output of each wave combined with its source, see [`Combine`]
+    combined: Vec<f32>,
+    /// State of the global filter
+    filter: FilterState,
+}
+
+impl Voice {
+    pub fn new(sample_rate: u32, hz: f32, timbre: &Timbre) -> Self {
+        let mut voice = Self {
+            sample_rate,
+            sample: 0,
+            age: 0,
+            released_at: None,
+            key: 0,
+            hz,
+            velocity: 1.0,
+            fade: None,
+            started: 0,
+            level: 0.0,
+            waves: vec![],
+            order: vec![],
+            pending: vec![],
+            heard: vec![],
+            combined: vec![],
+            filter: FilterState::default(),
+        };
+        voice.set_timbre(timbre);
+        voice
+    }
+
+    /// Starts a note over, reusing the buffers. With `retrigger`, keeps the
+    /// phases and filters so the note does not click.
+    fn restart(&mut self, key: u32, hz: f32, velocity: f32, started: u64, retrigger: bool) {
+        self.sample = 0;
+        self.age = 0;
+        self.released_at = None;
+        self.key = key;
+        self.hz = hz;
+        self.velocity = velocity;
+        self.fade = None;
+        self.started = started;
+        self.level = 0.0;
+        if !retrigger {
+            self.waves.fill(WaveState::default());
+            self.combined.fill(0.0);
+            self.filter = FilterState::default();
+        }
+    }
+
+    pub fn set_hz(&mut self, hz: f32) {
+        self.hz = hz;
+    }
+
+    /// Adapts the per-wave state to a changed timbre, reusing the buffers so
+    /// this only allocates when waves are added
+    pub fn set_timbre(&mut self, timbre: &Timbre) {
+        self.waves.resize(timbre.waves.len(), WaveState::default());
+        // With a modulation cycle, which `Timbre::from_json` rejects, only the
+        // waves that are heard are computed, without modulation
+        if timbre
+            .modulation_order_into(&mut self.order, &mut self.pending)
+            .is_err()
+        {
+            self.order.clear();
+            self.order
+                .extend((0..timbre.waves.len()).filter(|&i| timbre.waves[i].modulates.is_none()));
+        }
+        self.heard.clear();
+        self.heard
+            .extend(timbre.waves.iter().map(|wave| wave.modulates.is_none()));
+        for (i, wave) in timbre.waves.iter().enumerate() {
+            if let Some(source) = wave.combine.source(i) {
+                self.heard[source] = false;
+            }
+        }
+        self.combined.resize(timbre.waves.len(), 0.0);
+    }
+
+    /// Leaves the sustain loop and plays the rest of the timeline
+    pub fn release(&mut self, timbre: &Timbre) {
+        self.released_at = Some(self.age);
+        if let Some(sustain) = timbre.sustain {
+            let end = (sustain.end * self.sample_rate as f32) as u32;
+            self.sample = self.sample.max(end);
+        }
+    }
+
+    /// Whether the voice has faded out at the end of the note
+    pub fn is_finished(&self) -> bool {
+        self.fade
+            .is_some_and(|fade| fade as f32 >= FADE_SECONDS * self.sample_rate as f32)
+    }
+
+    fn is_released(&self) -> bool {
+        self.released_at.is_some()
+    }
+
+    fn time(&self) -> NoteTime {
+        let sample_rate = self.sample_rate as f32;
+        NoteTime {
+            timeline: self.sample as f32 / sample_rate,
+            age: self.age as f32 / sample_rate,
+            released: self
+                .released_at
+                .map(|at| (self.age - at) as f32 / sample_rate),
+        }
+    }
+
+    /// Returns the next sample
+    pub fn next(&mut self, timbre: &Timbre) -> f32 {
+        let sample_rate = self.sample_rate as f32;
+        let time = self.time();
+        for &i in &self.order {
+            let modulation: f32 = timbre
+                .waves
+                .iter()
+                .zip(&self.waves)
+                .filter_map(|(wave, state)| {
+                    let modulation = wave.modulates.as_ref()?;
+                    (modulation.target == i)
+                        .then(|| modulation.index.at(time.timeline) * state.output)
+                })
+                .sum();
+            timbre.waves[i].next(&mut self.waves[i], modulation, time, self.hz, sample_rate);
+        }
+        // Sources come first, so they are already combined themselves
+        for (i, wave) in timbre.waves.iter().enumerate() {
+            let output = self.waves[i].output;
+            self.combined[i] = match (wave.combine, wave.combine.source(i)) {
+                (Combine::Ring { .. }, Some(source)) => self.combined[source] * output,
+                (Combine::Am { depth, .. }, Some(source)) => {
+                    self.combined[source] * (1.0 + depth * (output - 1.0) / 2.0)
+                }
+                _ => output,
+            };
+        }
+        let mut value = self
+            .combined
+            .iter()
+            .zip(&self.heard)
+            .filter(|(_, heard)| **heard)
+            .map(|(value, _)| value)
+            .sum::<f32>();
+        if let Some(filter) = &timbre.filter {
+            value = filter.next(&mut self.filter, value, time, self.hz, sample_rate);
+        }
+        let value = value * timbre.amp.at(time);
+
+        self.sample = self.sample.saturating_add(1);
+        self.age = self.age.saturating_add(1);
+        if !self.is_released()
+            && let Some(sustain) = timbre.sustain
+        {
+            let end = (sustain.end * sample_rate) as u32;
+            if self.sample > end {
+                self.sample = ((sustain.start * sample_rate) as u32).min(end);
+            }
+        }
+        // A global envelope ends the note when its release does, otherwise
+        // the timeline ends it
+        let ended = match &timbre.amp {
+            Control::Envelope(envelope) => envelope.is_finished(time),
+            Control::Curve(_) => time.timeline >= timbre.length,
+        };
+        if ended {
+            *self.fade.get_or_insert(0) += 1;
+        }
+        let gain = match self.fade {
+            Some(fade) => (1.0 - fade as f32 / (FADE_SECONDS * sample_rate)).max(0.0),
+            None => 1.0,
+        };
+        let value = value * self.velocity * gain;
+        self.level = value.abs().max(self.level * 0.999);
+        value
+    }
+}
+
+/// Which voice to replace when a note starts while all voices are in use
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum StealPolicy {
+    /// The voice started first
+    #[default]
+    Oldest,
+    /// The voice with the lowest recent peak level
+    Quietest,
+    /// A voice playing the same key, which is always retriggered, otherwise the oldest
+    SameNote,
+}
+
+/// Plays notes with a [`Timbre`] on a pool of voices
+pub struct Synth {
+    timbre: Timbre,
+    sample_rate: u32,
+    /// Voices playing a note
+    voices: Vec<Voice>,
+    /// Voices ready for new notes, so starting one does not allocate.
+    /// Together with `voices`, there are `max_voices`.
+    free: Vec<Voice>,
+    max_voices: usize,
+    steal: StealPolicy,
+    /// Counter for [`Voice::started`]
+    started: u64,
+}
+
+impl Synth {
+    /// Creates a silent synth with 8 voices
+    pub fn new(timbre: Timbre, sample_rate: u32) -> Self {
+        let mut synth = Self {
+            timbre,
+            sample_rate,
+            voices: vec![],
+            free: vec![],
+            max_voices: 0,
+            steal: StealPolicy::default(),
+            started: 0,
+        };
+        synth.set_polyphony(8, StealPolicy::default());
+        synth
+    }
+
+    pub fn timbre(&self) -> &Timbre {
+        &self.timbre
+    }
+
+    /// Replaces the timbre, returning the previous one
+    pub fn set_timbre(&mut self, timbre: Timbre) -> Timbre {
+        for voice in self.voices.iter_mut().chain(&mut self.free) {
+            voice.set_timbre(&timbre);
+        }
+        std::mem::replace(&mut self.timbre, timbre)
+    }
+
+    /// Sets the maximum number of simultaneous notes, at least 1.
+    /// Voices beyond the limit are stopped immediately.
+    pub fn set_polyphony(&mut self, max_voices: usize, steal: StealPolicy) {
+        self.max_voices = max_voices.max(1);
+        self.steal = steal;
+        if self.voices.len() > self.max_voices {
+            self.voices.sort_by_key(|voice| voice.started);
+            self.voices.drain(..self.voices.len() - self.max_voices);
+        }
+        let free = self.max_voices - self.voices.len();
+        self.free.truncate(free);
+        while self.free.len() < free {
+            self.free
+                .push(Voice::new(self.sample_rate, 0.0, &self.timbre));
+        }
+        self.voices.reserve(self.max_voices - self.voices.len());
+        self.free.reserve(self.max_voices - self.free.len());
+    }
+
+    /// Starts a note. `key` identifies the note, e.g. a MIDI note number,
+    /// and `velocity` is in range 0.0..=1.0.
+    pub fn note_on(&mut self, key: u32, hz: f32, velocity: f32) {
+        let started = self.started;
+        self.started += 1;
+
+        if self.steal == StealPolicy::SameNote
+            && let Some(voice) = self.voices.iter_mut().find(|voice| voice.key == key)
+        {
+            voice.restart(key, hz, velocity, started, true);
+            return;
+        }
+        self.note_off(key);
+        if let Some(mut voice) = self.free.pop() {
+            voice.restart(key, hz, velocity, started, false);
+            self.voices.push(voice);
+            return;
+        }
+        // Prefer voices that are already fading out
+        let steal = self
+            .voices
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| {
+                let order = b.is_released().cmp(&a.is_released());
+                order.then(match self.steal {
+                    StealPolicy::Oldest | StealPolicy::SameNote => a.started.cmp(&b.started),
+                    StealPolicy::Quietest => a.level.total_cmp(&b.level),
+                })
+            })
+            .map(|(i, _)| i);
+        if let Some(i) = steal {
+            self.voices[i].restart(key, hz, velocity, started, false);
+        }
+    }
+
+    /// Releases the notes started with `key`
+    pub fn note_off(&mut self, key: u32) {
+        for voice in &mut self.voices {
+            if voice.key == key && !voice.is_released() {
+                voice.release(&self.timbre);
+            }
+        }
+    }
+
+    /// Changes the frequency of the notes started with `key`
+    pub fn set_note_hz(&mut self, key: u32, hz: f32) {
+        for voice in &mut self.voices {
+            if voice.key == key {
+                voice.set_hz(hz);
+            }
+        }
+    }
+
+    /// Returns the next sample, mixing all voices
+    pub fn next_sample(&mut self) -> f32 {
+        let value = self
+            .voices
+            .iter_mut()
+            .map(|voice| voice.next(&self.timbre))
+            .sum();
+        let mut i = 0;
+        while i < self.voices.len() {
+            if self.voices[i].is_finished() {
+                self.free.push(self.voices.swap_remove(i));
+            } else {
+                i += 1;
+            }
+        }
+        value
+    }
+
+    /// Fills `buffer` with the next mono samples
+    pub fn fill(&mut self, buffer: &mut [f32]) {
+        for sample in buffer {
+            *sample = self.next_sample();
+        }
+    }
+}
+
+/// Renders a note of `timbre` at `hz` held for `hold` seconds, at most
+/// [`MAX_LENGTH`], followed by its release
+pub fn render(timbre: &Timbre, hz: f32, hold: f32, sample_rate: u32) -> Vec<f32> {
+    let mut voice = Voice::new(sample_rate, hz, timbre);
+    let hold = (hold.clamp(0.0, MAX_LENGTH) * sample_rate as f32) as usize;
+    let mut samples = vec![];
+    while !voice.is_finished() {
+        if samples.len() == hold {
+            voice.release(timbre);
+        }
+        samples.push(voice.next(timbre));
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voices_return_to_the_pool() {
+        let timbre = Timbre::from_json(
+            br#"{"amp": [1.0], "sustain": null, "length": 0.1,
+                "waves": [{"waveform": "Sine", "freq": [1.0], "amp": [1.0]}]}"#,
+        )
+        .unwrap();
+        let mut synth = Synth::new(timbre, 48000);
+        synth.set_polyphony(2, StealPolicy::Oldest);
+        for key in 0..3 {
+            synth.note_on(key, 440.0, 1.0);
+        }
+        assert_eq!((synth.voices.len(), synth.free.len()), (2, 0));
+        assert_eq!(synth.voices.iter().map(|voice| voice.key).min(), Some(1));
+        synth.fill(&mut [0.0; 48000]);
+        assert_eq!((synth.voices.len(), synth.free.len()), (0, 2));
+    }
+
+    /// Seconds rendered for `amp` with a note held for `hold` seconds
+    fn note_length(amp: &str, lifecycle: &str, hold: f32) -> f32 {
+        let json = format!(
+            r#"{{"amp": {amp}, {lifecycle}
+                "waves": [{{"waveform": "Sine", "freq": [1.0], "amp": [1.0]}}]}}"#
+        );
+        let timbre = Timbre::from_json(json.as_bytes()).unwrap();
+        render(&timbre, 440.0, hold, 1000).len() as f32 / 1000.0
+    }
+
+    #[test]
+    fn envelopes_decide_when_notes_end() {
+        let envelope = r#"{"attack": 0.01, "decay": 0.1, "sustain": 0.5, "release": 0.5}"#;
+        // Released in the default sustain loop, which ends with the timeline
+        assert_eq!(note_length(envelope, "", 0.5), 1.01);
+        // Held beyond the timeline
+        let once = r#""sustain": null, "length": 0.2,"#;
+        assert_eq!(note_length(envelope, once, 5.0), 5.51);
+        // Without an envelope, the timeline ends the note even while held
+        assert_eq!(note_length("[1.0]", once, 5.0), 0.21);
+    }
+
+    #[test]
+    fn render_bounds_the_hold() {
+        let timbre = Timbre::from_json(
+            br#"{"amp": [1.0], "length": 2.0,
+                "waves": [{"waveform": "Sine", "freq": [1.0], "amp": [1.0]}]}"#,
+        )
+        .unwrap();
+        // Sustained until released, then the last second of the timeline
+        assert_eq!(render(&timbre, 440.0, 1e12, 100).len(), 360101);
+        assert_eq!(render(&timbre, 440.0, f32::NAN, 100).len(), 101);
+    }
+
+    #[test]
+    fn set_timbre_reuses_buffers() {
+        let timbre = Timbre::from_json(
+            br#"{"amp": [1.0], "waves": [
+                {"waveform": "Sine", "freq": [1.0], "amp": [1.0],
+                 "modulates": {"target": 1, "index": [1.0]}},
+                {"waveform": "Sine", "freq": [1.0], "amp": [1.0]}
+            ]}"#,
+        )
+        .unwrap();
+        let mut voice = Voice::new(48000, 440.0, &timbre);
+        let buffers = (voice.order.as_ptr(), voice.heard.as_ptr());
+        let mut edited = timbre.clone();
+        edited.waves[0].modulates = None;
+        voice.set_timbre(&edited);
+        assert_eq!((voice.order.as_ptr(), voice.heard.as_ptr()), buffers);
+        assert_eq!(
+            (&voice.order[..], &voice.heard[..]),
+            (&[0, 1][..], &[true, true][..])
+        );
+        voice.set_timbre(&timbre);
+        assert_eq!(
+            (&voice.order[..], &voice.heard[..]),
+            (&[0, 1][..], &[false, true][..])
+        );
+    }
+
+    #[test]
+    fn phase_modulation_stays_in_range() {
+        // Feedback and modulation push the phase below 0
+        let timbre = Timbre::from_json(
+            br#"{"amp": [1.0], "sustain": null, "length": 0.5, "waves": [
+                {"waveform": "Square", "bandlimited": true, "freq": [1.0], "amp": [1.0],
+                 "feedback": 2.0},
+                {"waveform": "Sine", "freq": [2.0], "amp": [1.0],
+                 "modulates": {"target": 2, "index": [8.0]}},
+                {"waveform": "Sawtooth", "bandlimited": true, "freq": [1.0], "amp": [1.0]},
+                {"waveform": "Triangle", "bandlimited": true, "freq": [1.0], "amp": [1.0],
+                 "feedback": 3.0}
+            ]}"#,
+        )
+        .unwrap();
+        for i in 0..timbre.waves.len() {
+            let mut solo = timbre.clone();
+            for (j, wave) in solo.waves.iter_mut().enumerate() {
+                if j != i && wave.modulates.is_none() {
+                    wave.amp = crate::Curve::constant(0.0).into();
+                }
+            }
+            let peak = render(&solo, 440.0, 0.5, 48000)
+                .into_iter()
+                .fold(0.0f32, |peak, x| peak.max(x.abs()));
+            assert!(peak <= 1.0, "wave {} peaks at {peak}", i + 1);
+        }
+    }
+}
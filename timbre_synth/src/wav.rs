@@ -0,0 +1,254 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+/// Sample format of a WAV file
+#[derive(Clone, Copy, PartialEq)]
+pub enum BitDepth {
+    Int16,
+    Int24,
+    Int32,
+    Float32,
+}
+
+impl BitDepth {
+    pub const ALL: [Self; 4] = [Self::Int16, Self::Int24, Self::Int32, Self::Float32];
+
+    fn bits(self) -> u16 {
+        match self {
+            Self::Int16 => 16,
+            Self::Int24 => 24,
+            Self::Int32 | Self::Float32 => 32,
+        }
+    }
+}
+
+impl fmt::Display for BitDepth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Float32 => write!(f, "32-bit float"),
+            _ => write!(f, "{}-bit", self.bits()),
+        }
+    }
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+/// Format whose actual format is given by a subformat GUID
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Writes mono `samples` as a RIFF/WAVE file, failing if they do not fit in
+/// its 4 GiB
+pub fn write(
+    mut writer: impl Write,
+    samples: &[f32],
+    sample_rate: u32,
+    bit_depth: BitDepth,
+) -> io::Result<()> {
+    let block_align = bit_depth.bits() / 8;
+    let float = bit_depth == BitDepth::Float32;
+    // Non-PCM formats need the cbSize field and a fact chunk
+    let fmt_len: u32 = if float { 18 } else { 16 };
+    let fact_len: u32 = if float { 12 } else { 0 };
+    let too_long = || {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too many samples for a WAV file",
+        )
+    };
+    let data_len = u32::try_from(samples.len())
+        .ok()
+        .and_then(|len| len.checked_mul(block_align as u32))
+        .ok_or_else(too_long)?;
+    let riff_len = (4 + 8 + fmt_len + fact_len + 8 + data_len % 2)
+        .checked_add(data_len)
+        .ok_or_else(too_long)?;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&riff_len.to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&fmt_len.to_le_bytes())?;
+    let format = if float {
+        WAVE_FORMAT_IEEE_FLOAT
+    } else {
+        WAVE_FORMAT_PCM
+    };
+    writer.write_all(&format.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&sample_rate.to_le_bytes())?;
+    writer.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+    writer.write_all(&block_align.to_le_bytes())?;
+    writer.write_all(&bit_depth.bits().to_le_bytes())?;
+    if float {
+        writer.write_all(&0u16.to_le_bytes())?;
+        writer.write_all(b"fact")?;
+        writer.write_all(&4u32.to_le_bytes())?;
+        writer.write_all(&(samples.len() as u32).to_le_bytes())?;
+    }
+
+    writer.write_all(b"data")?;
+    writer.write_all(&data_len.to_le_bytes())?;
+    write_pcm(&mut writer, samples, bit_depth)?;
+    if data_len % 2 == 1 {
+        writer.write_all(&[0])?;
+    }
+    writer.flush()
+}
+
+/// Writes headerless little-endian `samples`
+pub fn write_pcm(mut writer: impl Write, samples: &[f32], bit_depth: BitDepth) -> io::Result<()> {
+    for &sample in samples {
+        let clamped = sample.clamp(-1.0, 1.0);
+        match bit_depth {
+            BitDepth::Int16 => {
+                writer.write_all(&((clamped * i16::MAX as f32) as i16).to_le_bytes())?
+            }
+            BitDepth::Int24 => {
+                writer.write_all(&((clamped * 8388607.0) as i32).to_le_bytes()[..3])?
+            }
+            BitDepth::Int32 => {
+                writer.write_all(&((clamped as f64 * i32::MAX as f64) as i32).to_le_bytes())?
+            }
+            BitDepth::Float32 => writer.write_all(&sample.to_le_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+/// A WAV file read by [`read`], mixed down to mono
+pub struct Wav {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    /// Text of the `clm ` chunk, which Serum writes to wavetables to give
+    /// their frame size
+    pub clm: Option<String>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads a RIFF/WAVE file of 8, 16, 24 or 32-bit integer samples or 32 or
+/// 64-bit float samples
+pub fn read(mut reader: impl Read) -> io::Result<Wav> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(invalid("not a WAV file"));
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut clm = None;
+    let mut rest = &bytes[12..];
+    while rest.len() >= 8 {
+        let id = &rest[..4];
+        let len = u32::from_le_bytes(rest[4..8].try_into().unwrap()) as usize;
+        let chunk = &rest[8..(8 + len).min(rest.len())];
+        match id {
+            b"fmt " => format = Some(chunk),
+            b"data" => data = Some(chunk),
+            b"clm " => clm = Some(String::from_utf8_lossy(chunk).into_owned()),
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        rest = &rest[(8 + len + len % 2).min(rest.len())..];
+    }
+    let format = format.filter(|format| format.len() >= 16);
+    let (Some(format), Some(data)) = (format, data) else {
+        return Err(invalid("the WAV file has no format or no data"));
+    };
+
+    let u16_at = |i: usize| u16::from_le_bytes([format[i], format[i + 1]]);
+    let mut tag = u16_at(0);
+    let channels = u16_at(2) as usize;
+    let sample_rate = u32::from_le_bytes(format[4..8].try_into().unwrap());
+    let bits = u16_at(14);
+    if tag == WAVE_FORMAT_EXTENSIBLE && format.len() >= 26 {
+        tag = u16_at(24);
+    }
+    let decode: fn(&[u8]) -> f32 = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 8) => |b| (b[0] as f32 - 128.0) / 128.0,
+        (WAVE_FORMAT_PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0,
+        (WAVE_FORMAT_PCM, 24) => {
+            |b| i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.0
+        }
+        (WAVE_FORMAT_PCM, 32) => {
+            |b| i32::from_le_bytes(b.try_into().unwrap()) as f32 / 2147483648.0
+        }
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => |b| f32::from_le_bytes(b.try_into().unwrap()),
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => |b| f64::from_le_bytes(b.try_into().unwrap()) as f32,
+        _ => return Err(invalid("unsupported WAV sample format")),
+    };
+    if channels == 0 {
+        return Err(invalid("the WAV file has no channels"));
+    }
+
+    let width = bits as usize / 8;
+    let samples = data
+        .chunks_exact(width * channels)
+        .map(|frame| frame.chunks_exact(width).map(decode).sum::<f32>() / channels as f32)
+        .collect();
+    Ok(Wav {
+        samples,
+        sample_rate,
+        clm,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_each_bit_depth() {
+        // An odd number of 24-bit samples needs a padding byte
+        let samples = [0.0, 0.5, -0.5, 0.25, -1.0];
+        for bit_depth in BitDepth::ALL {
+            let mut bytes = vec![];
+            write(&mut bytes, &samples, 48000, bit_depth).unwrap();
+            assert_eq!(bytes.len() % 2, 0, "{bit_depth}");
+            let wav = read(&bytes[..]).unwrap();
+            assert_eq!(wav.sample_rate, 48000);
+            assert!(wav.clm.is_none());
+            assert_eq!(wav.samples.len(), samples.len(), "{bit_depth}");
+            for (read, written) in wav.samples.iter().zip(samples) {
+                assert!(
+                    (read - written).abs() < 1e-4,
+                    "{bit_depth}: {read} != {written}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn reads_clm_chunks_and_mixes_channels() {
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        bytes.extend(b"fmt \x10\0\0\0");
+        bytes.extend(WAVE_FORMAT_PCM.to_le_bytes());
+        bytes.extend(2u16.to_le_bytes());
+        bytes.extend(44100u32.to_le_bytes());
+        bytes.extend((44100u32 * 4).to_le_bytes());
+        bytes.extend(4u16.to_le_bytes());
+        bytes.extend(16u16.to_le_bytes());
+        // Odd length, so followed by a padding byte
+        bytes.extend(b"clm \x03\0\0\0<!>\0");
+        bytes.extend(b"data\x08\0\0\0");
+        for sample in [16384i16, 0, -16384, -16384] {
+            bytes.extend(sample.to_le_bytes());
+        }
+        let wav = read(&bytes[..]).unwrap();
+        assert_eq!(wav.sample_rate, 44100);
+        assert_eq!(wav.clm.as_deref(), Some("<!>"));
+        assert_eq!(wav.samples, [0.25, -0.5]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(read(&b"RIFF\0\0\0\0AVI "[..]).is_err());
+        assert!(read(&b"RIFF\0\0\0\0WAVE"[..]).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use std::f32::consts::TAU;
+
+use serde::{Deserialize, Serialize};
+
+use crate::Wavetable;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Waveform {
+    Sine,
+    Triangle,
+    Sawtooth,
+    Square,
+    WhiteNoise,
+    /// Cycles drawn by the user or imported from WAV files
+    Table(Wavetable),
+}
+
+impl Waveform {
+    /// Value at `t` cycles.
+    ///
+    /// `width` is the part of the cycle a square is high, or the position of
+    /// the peak of a triangle, which becomes a rising sawtooth at 1.0 and a
+    /// falling one at 0.0. 0.5 gives the symmetric waveforms. `position` is
+    /// the position in the frames of a table, as in [`Wavetable::at`]. Other
+    /// waveforms ignore them.
+    pub fn at(&self, t: f64, width: f32, position: f32) -> f32 {
+        let fract = t.rem_euclid(1.0) as f32;
+        let width = width.clamp(0.0, 1.0);
+        match self {
+            Self::Sine => (fract * TAU).sin(),
+            Self::Triangle => {
+                if fract < width {
+                    fract * 2.0 / width - 1.0
+                } else {
+                    1.0 - (fract - width) * 2.0 / (1.0 - width)
+                }
+            }
+            Self::Sawtooth => fract * 2.0 - 1.0,
+            Self::Square => {
+                if fract < 1.0 - width {
+                    -1.0
+                } else {
+                    1.0
+                }
+            }
+            Self::WhiteNoise => {
+                let bits = t.to_bits();
+                let mut n = (bits ^ bits >> 32) as u32;
+                // fmix32 (MurmurHash3)
+                n = (n ^ n >> 16).wrapping_mul(0x85EBCA6B);
+                n = (n ^ n >> 13).wrapping_mul(0xC2B2AE35);
+                n ^= n >> 16;
+                n as f32 / i32::MAX as f32 - 1.0
+            }
+            Self::Table(table) => table.at(t, position),
+        }
+    }
+
+    /// Value at `t` cycles with discontinuities smoothed by PolyBLEP/PolyBLAMP
+    /// to reduce aliasing, or for tables from a band-limited copy, where `dt` is
+    /// the phase increment per sample
+    pub fn bandlimited_at(&self, t: f64, dt: f32, width: f32, position: f32) -> f32 {
+        let fract = t.rem_euclid(1.0) as f32;
+        let dt = dt.clamp(f32::EPSILON, 0.5);
+        match self {
+            Self::Triangle => {
+                // Keep both slopes at least a sample long, so the corners
+                // stay finite and PolyBLAMP can smooth them
+                let width = width.clamp(dt, 1.0 - dt);
+                let peak = (fract + 1.0 - width).fract();
+                let bend = 2.0 / width + 2.0 / (1.0 - width);
+                self.at(t, width, position)
+                    + bend * dt * (poly_blamp(fract, dt) - poly_blamp(peak, dt))
+            }
+            Self::Sawtooth => self.at(t, width, position) - poly_blep(fract, dt),
+            Self::Square => {
+                let rise = (fract + width.clamp(0.0, 1.0)).fract();
+                self.at(t, width, position) - poly_blep(fract, dt) + poly_blep(rise, dt)
+            }
+            Self::Table(table) => table.bandlimited_at(t, dt, position),
+            Self::Sine | Self::WhiteNoise => self.at(t, width, position),
+        }
+    }
+}
+
+/// Residual of a band-limited unit step at phase 0.0
+fn poly_blep(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt;
+        2.0 * t - t * t - 1.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// Residual of a band-limited unit ramp at phase 0.0, in samples
+fn poly_blamp(t: f32, dt: f32) -> f32 {
+    if t < dt {
+        let t = t / dt - 1.0;
+        -t * t * t / 3.0
+    } else if t > 1.0 - dt {
+        let t = (t - 1.0) / dt + 1.0;
+        t * t * t / 3.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fft;
+
+    const N: usize = 4096;
+    /// Cycles per `N` samples, prime so no folded harmonic lands on a harmonic
+    const CYCLES: usize = 307;
+
+    /// Share of the energy outside the harmonic bins, which is all aliasing
+    /// since `N` samples hold a whole number of cycles
+    fn aliasing(f: impl Fn(f64) -> f32) -> f32 {
+        let mut re: Vec<f32> = (0..N).map(|i| f((i * CYCLES) as f64 / N as f64)).collect();
+        let mut im = vec![0.0; N];
+        fft(&mut re, &mut im);
+        let energy = |bin: usize| re[bin] * re[bin] + im[bin] * im[bin];
+        let total: f32 = (1..N / 2).map(energy).sum();
+        let harmonic: f32 = (CYCLES..N / 2).step_by(CYCLES).map(energy).sum();
+        (total - harmonic) / total
+    }
+
+    #[test]
+    fn bandlimited_waveforms_alias_less() {
+        let dt = CYCLES as f32 / N as f32;
+        for waveform in [Waveform::Sawtooth, Waveform::Square, Waveform::Triangle] {
+            let naive = aliasing(|t| waveform.at(t, 0.5, 0.0));
+            let bandlimited = aliasing(|t| waveform.bandlimited_at(t, dt, 0.5, 0.0));
+            assert!(
+                bandlimited < naive / 2.0,
+                "{waveform:?}: {bandlimited} of the energy aliases, {naive} without band-limiting"
+            );
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::{f64::consts::TAU, fmt, sync::Arc};
+
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{fft, wav::Wav};
+
+/// Number of samples in each cycle of a [`Wavetable`]
+pub const TABLE_SIZE: usize = 2048;
+/// Number of band-limited copies, down to one with only the fundamental
+const LEVELS: usize = TABLE_SIZE.ilog2() as usize;
+/// Frame size of Serum wavetables whose `clm ` chunk does not give one
+const SERUM_FRAME_SIZE: usize = 2048;
+
+/// Cycles of [`TABLE_SIZE`] samples, called frames, played by
+/// [`crate::Waveform::Table`] at a position that moves across them.
+///
+/// Keeps band-limited copies of each frame, each with half the harmonics of
+/// the one before, so high notes can be played without aliasing.
+#[derive(Clone, Deserialize)]
+#[serde(try_from = "TableRepr")]
+pub struct Wavetable {
+    /// Each frame followed by its band-limited copies. Shared, since timbres
+    /// are cloned for every edit.
+    frames: Vec<Arc<[Vec<f32>]>>,
+}
+
+/// Serialized forms of a [`Wavetable`], with a single frame written as a
+/// plain array
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TableRepr {
+    Cycle(Vec<f32>),
+    Frames(Vec<Vec<f32>>),
+}
+
+impl Wavetable {
+    /// Samples `f` at [`TABLE_SIZE`] points across a cycle from 0.0 to 1.0
+    pub fn from_fn(mut f: impl FnMut(f32) -> f32) -> Self {
+        let cycle = (0..TABLE_SIZE)
+            .map(|i| f(i as f32 / TABLE_SIZE as f32))
+            .collect();
+        Self {
+            frames: vec![mipmaps(cycle)],
+        }
+    }
+
+    /// Makes a table from a single-cycle WAV, or from the frames of a
+    /// wavetable WAV written by Serum, resampling each cycle to [`TABLE_SIZE`]
+    pub fn from_wav(wav: &Wav) -> Result<Self, String> {
+        let frame_size = match &wav.clm {
+            Some(clm) => clm
+                .strip_prefix("<!>")
+                .and_then(|rest| rest.split_whitespace().next()?.parse().ok())
+                .unwrap_or(SERUM_FRAME_SIZE),
+            None => wav.samples.len(),
+        };
+        if frame_size < 2 || wav.samples.len() < frame_size {
+            return Err(format!(
+                "the WAV file has {} samples, which is not enough for a cycle",
+                wav.samples.len()
+            ));
+        }
+        let frames = wav
+            .samples
+            .chunks_exact(frame_size)
+            .map(|cycle| mipmaps(resample(cycle)))
+            .collect();
+        Ok(Self { frames })
+    }
+
+    /// Number of frames, at least 1
+    pub fn frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn frame(&self, frame: usize) -> &[f32] {
+        &self.frames[frame][0]
+    }
+
+    /// Changes the samples of `frame`, then updates its band-limited copies
+    pub fn edit(&mut self, frame: usize, f: impl FnOnce(&mut [f32])) {
+        let mut cycle = self.frames[frame][0].clone();
+        f(&mut cycle);
+        self.frames[frame] = mipmaps(cycle);
+    }
+
+    /// Value at `t` cycles, interpolating between samples and between the
+    /// frames around `position`, from 0.0 for the first to 1.0 for the last
+    pub fn at(&self, t: f64, position: f32) -> f32 {
+        self.interpolate(t, position, 0)
+    }
+
+    /// Like [`Wavetable::at`], but from the copies with the most harmonics
+    /// that stay below Nyquist, where `dt` is the phase increment per sample
+    pub fn bandlimited_at(&self, t: f64, dt: f32, position: f32) -> f32 {
+        let level = ((TABLE_SIZE as f32 * dt).log2().floor() + 1.0).clamp(0.0, (LEVELS - 1) as f32);
+        self.interpolate(t, position, level as usize)
+    }
+
+    fn interpolate(&self, t: f64, position: f32, level: usize) -> f32 {
+        let position = position.clamp(0.0, 1.0) * (self.frames.len() - 1) as f32;
+        let i = position as usize;
+        let a = lerp(&self.frames[i][level], t);
+        match self.frames.get(i + 1) {
+            Some(next) => a + (lerp(&next[level], t) - a) * position.fract(),
+            None => a,
+        }
+    }
+}
+
+fn lerp(samples: &[f32], t: f64) -> f32 {
+    let position = t.rem_euclid(1.0) as f32 * samples.len() as f32;
+    let i = position as usize % samples.len();
+    let next = samples[(i + 1) % samples.len()];
+    let fract = position.fract();
+    samples[i] + (next - samples[i]) * fract
+}
+
+/// `cycle` followed by copies keeping only the harmonics up to
+/// `TABLE_SIZE >> (level + 1)`, each with at least four samples per cycle of
+/// its highest harmonic
+fn mipmaps(cycle: Vec<f32>) -> Arc<[Vec<f32>]> {
+    debug_assert_eq!(cycle.len(), TABLE_SIZE);
+    let mut re = cycle.clone();
+    let mut im = vec![0.0; TABLE_SIZE];
+    fft(&mut re, &mut im);
+    let mut levels = vec![cycle];
+    levels.extend((1..LEVELS).map(|level| {
+        let harmonics = TABLE_SIZE >> (level + 1);
+        let size = (harmonics * 4).min(TABLE_SIZE);
+        // Copy the kept harmonics and their mirror images, conjugated to
+        // transform back with the forward FFT
+        let mut level_re = vec![0.0; size];
+        let mut level_im = vec![0.0; size];
+        for k in 0..=harmonics {
+            level_re[k] = re[k];
+            level_im[k] = -im[k];
+        }
+        for k in 1..=harmonics {
+            level_re[size - k] = re[TABLE_SIZE - k];
+            level_im[size - k] = -im[TABLE_SIZE - k];
+        }
+        fft(&mut level_re, &mut level_im);
+        level_re
+            .into_iter()
+            .map(|re| re / TABLE_SIZE as f32)
+            .collect()
+    }));
+    levels.into()
+}
+
+/// Resamples a cycle of any length to [`TABLE_SIZE`] samples by summing its
+/// harmonics, dropping any that do not fit
+fn resample(cycle: &[f32]) -> Vec<f32> {
+    let len = cycle.len();
+    if len == TABLE_SIZE {
+        return cycle.to_vec();
+    }
+    let harmonics = ((len - 1) / 2).min(TABLE_SIZE / 2 - 1);
+    let twiddles: Vec<(f64, f64)> = (0..len)
+        .map(|i| (-TAU * i as f64 / len as f64).sin_cos())
+        .collect();
+    let mut re = vec![0.0; TABLE_SIZE];
+    let mut im = vec![0.0; TABLE_SIZE];
+    for k in 0..=harmonics {
+        let (mut sum_re, mut sum_im) = (0.0, 0.0);
+        for (i, &sample) in cycle.iter().enumerate() {
+            let (sin, cos) = twiddles[k * i % len];
+            sum_re += sample as f64 * cos;
+            sum_im += sample as f64 * sin;
+        }
+        // Conjugated as in `mipmaps`
+        re[k] = sum_re as f32;
+        im[k] = -sum_im as f32;
+        if k > 0 {
+            re[TABLE_SIZE - k] = sum_re as f32;
+            im[TABLE_SIZE - k] = sum_im as f32;
+        }
+    }
+    fft(&mut re, &mut im);
+    re.into_iter().map(|re| re / len as f32).collect()
+}
+
+impl TryFrom<TableRepr> for Wavetable {
+    type Error = String;
+
+    fn try_from(repr: TableRepr) -> Result<Self, Self::Error> {
+        let frames = match repr {
+            TableRepr::Cycle(cycle) => vec![cycle],
+            TableRepr::Frames(frames) => frames,
+        };
+        if frames.is_empty() {
+            return Err("a wavetable needs at least one frame".to_owned());
+        }
+        if let Some(frame) = frames.iter().find(|frame| frame.len() != TABLE_SIZE) {
+            return Err(format!(
+                "a wavetable needs {TABLE_SIZE} samples per frame, not {}",
+                frame.len()
+            ));
+        }
+        Ok(Self {
+            frames: frames.into_iter().map(mipmaps).collect(),
+        })
+    }
+}
+
+impl Serialize for Wavetable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let [frame] = &self.frames[..] {
+            frame[0].serialize(serializer)
+        } else {
+            serializer.collect_seq(self.frames.iter().map(|frame| &frame[0]))
+        }
+    }
+}
+
+impl PartialEq for Wavetable {
+    fn eq(&self, other: &Self) -> bool {
+        self.frames.len() == other.frames.len()
+            && self
+                .frames
+                .iter()
+                .zip(&other.frames)
+                .all(|(a, b)| Arc::ptr_eq(a, b) || a[0] == b[0])
+    }
+}
+
+impl fmt::Debug for Wavetable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.frames.iter().map(|frame| &frame[0]))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::TAU;
+
+    use super::*;
+
+    fn wav(samples: usize, clm: Option<&str>) -> Wav {
+        Wav {
+            samples: (0..samples).map(|i| i as f32).collect(),
+            sample_rate: 44100,
+            clm: clm.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn splits_serum_frames() {
+        let serum = Some("<!>256 10000000 wavetable (www.xferrecords.com)");
+        // The partial frame at the end is dropped
+        let table = Wavetable::from_wav(&wav(256 * 3 + 100, serum)).unwrap();
+        assert_eq!(table.frames(), 3);
+        for garbage in ["<!>", "<!>abc 10000000", "Serum"] {
+            let table = Wavetable::from_wav(&wav(SERUM_FRAME_SIZE * 2, Some(garbage))).unwrap();
+            assert_eq!(table.frames(), 2, "{garbage}");
+            assert_eq!(table.frame(1)[0], SERUM_FRAME_SIZE as f32, "{garbage}");
+        }
+        assert!(Wavetable::from_wav(&wav(100, serum)).is_err());
+    }
+
+    #[test]
+    fn reads_single_cycles() {
+        let table = Wavetable::from_wav(&wav(TABLE_SIZE * 2, None)).unwrap();
+        assert_eq!(table.frames(), 1);
+        assert!(Wavetable::from_wav(&wav(1, None)).is_err());
+    }
+
+    #[test]
+    fn resampling_keeps_sines() {
+        for len in [100, 300, 4096] {
+            let cycle: Vec<f32> = (0..len)
+                .map(|i| (TAU * i as f32 / len as f32).sin())
+                .collect();
+            let resampled = resample(&cycle);
+            assert_eq!(resampled.len(), TABLE_SIZE);
+            for (i, sample) in resampled.iter().enumerate() {
+                let sine = (TAU * i as f32 / TABLE_SIZE as f32).sin();
+                assert!(
+                    (sample - sine).abs() < 1e-3,
+                    "{len}: {sample} != {sine} at {i}"
+                );
+            }
+        }
+    }
+}